@@ -1,5 +1,9 @@
 let split = import("./split-grid.js");
 const TIMEOUT = 500;
+// Packages, which failed to install automatically, are only tried again after this many ms
+const INSTALL_RETRY_TIMEOUT = 60 * 1000;
+const REGISTRIES_KEY = "typst_live.registries";
+const AUTO_INSTALL_KEY = "typst_live.auto_install";
 const FONT_NAMES = [
 	"DejaVuSansMono-BoldOblique.ttf",
 	"LinLibertine_RBI.ttf",
@@ -25,33 +29,62 @@ function debounce(fn, timeout) {
 	};
 }
 
+function packageName(pkg) {
+	let version = pkg.version;
+	return `@${pkg.namespace}/${pkg.name}:${version.major}.${version.minor}.${version.patch}`;
+}
+
+function formatBytes(bytes) {
+	if (bytes < 1000) return `${bytes} B`;
+	if (bytes < 1000 * 1000) return `${(bytes / 1000).toFixed(1)} kB`;
+	return `${(bytes / 1000 / 1000).toFixed(1)} MB`;
+}
+
 class PackageManager {
-	constructor(bindings) {
-		this.bindings = new bindings.PackageManager();
+	// `onChange` is called after packages were installed or removed
+	constructor(bindings, storage, typst, onChange) {
+		this.bindings = bindings.PackageManager.with_storage(storage);
+		this.typst = typst;
+		this.onChange = onChange;
 		this.enablePackageInstallation();
+		this.enablePackageRemoval();
 	}
 
-	packageToRow(pkg) {
+	// `depth` is the nesting level in the dependency tree
+	packageToRow(pkg, depth, installed) {
 		let row = document.createElement("tr");
 		let name = document.createElement("td");
 		let namespace = document.createElement("td");
 		let version = document.createElement("td");
+		let description = document.createElement("td");
 		let deleteElement = document.createElement("td");
 		let deleteButton = document.createElement("button");
 		deleteButton.addEventListener("click", (_) => {
-			this.bindings.delete_package(pkg);
-			this.updatePackageList();
+			let freed = this.bindings.delete_package(pkg);
+			this.showStatus(`Removed ${packageName(pkg)}, freed ${formatBytes(freed)}`);
+			this.packagesChanged();
 		});
 		deleteButton.textContent = "X";
-		deleteElement.appendChild(deleteButton);
-		name.textContent = pkg.name;
+		if (installed) deleteElement.appendChild(deleteButton);
+		name.textContent = (depth > 0 ? "└ " : "") + pkg.name;
+		name.style.paddingLeft = `${depth * 1.5}em`;
 		namespace.textContent = pkg.namespace;
 		version.textContent =
 			pkg.version.major + "." + pkg.version.minor + "." + pkg.version.patch;
+		let info = installed ? this.bindings.package_info(pkg) : null;
+		if (!installed) {
+			description.textContent = "not installed";
+		} else if (info != null) {
+			description.textContent = info.description ?? "";
+			description.title = [info.authors.join(", "), info.license]
+				.filter((text) => text)
+				.join("\n");
+		}
 
 		row.appendChild(namespace);
 		row.appendChild(name);
 		row.appendChild(version);
+		row.appendChild(description);
 		row.appendChild(deleteElement);
 		return row;
 	}
@@ -59,28 +92,134 @@ class PackageManager {
 	updatePackageList() {
 		let packageList = document.getElementById("package-list");
 		let packages = this.bindings.list_packages();
-		let rows = packages.map(this.packageToRow);
+		let dependencies = new Map(
+			packages.map((pkg) => [packageName(pkg), this.bindings.package_dependencies(pkg)]),
+		);
+		let dependents = new Set(
+			[...dependencies.values()].flat().map((pkg) => packageName(pkg)),
+		);
+		let rows = [];
+		let shown = new Set();
+		let addRows = (pkg, depth, ancestors) => {
+			let name = packageName(pkg);
+			shown.add(name);
+			rows.push(this.packageToRow(pkg, depth, dependencies.has(name)));
+			for (let dependency of dependencies.get(name) ?? []) {
+				if (ancestors.has(packageName(dependency))) continue;
+				addRows(dependency, depth + 1, new Set([...ancestors, name]));
+			}
+		};
+		// Packages which no other package depends on are the roots of the tree
+		packages
+			.filter((pkg) => !dependents.has(packageName(pkg)))
+			.forEach((pkg) => addRows(pkg, 0, new Set()));
+		// Packages which only depend on each other
+		packages.forEach((pkg) => {
+			if (!shown.has(packageName(pkg))) addRows(pkg, 0, new Set());
+		});
 		packageList.replaceChildren(...rows);
 	}
 
+	packagesChanged() {
+		this.updatePackageList();
+		this.onChange();
+	}
+
+	// The first URL is the registry, the others are mirrors which are tried in order
+	get registries() {
+		return [this.bindings.registry, ...this.bindings.mirrors];
+	}
+
+	set registries(urls) {
+		if (urls.length === 0) return;
+		this.bindings.registry = urls[0];
+		this.bindings.mirrors = urls.slice(1);
+	}
+
 	enablePackageInstallation() {
 		let packageInput = document.getElementById("package-input");
 		packageInput.addEventListener("keydown", (event) => {
 			if (event.key === "Enter") {
+				this.showError(null);
 				this.bindings
 					.download_package_from_str(packageInput.value)
 					.then((_) => {
-						this.updatePackageList();
+						this.packagesChanged();
 						packageInput.value = "";
-					});
+					})
+					.catch((error) => this.showError(error));
 			}
 		});
 		packageInput.value = "";
+		// The directory input keeps the paths of the files in `webkitRelativePath`
+		for (let id of ["local-package-input", "local-package-directory-input"]) {
+			let localInput = document.getElementById(id);
+			localInput.addEventListener("change", async () => {
+				this.showError(null);
+				try {
+					let spec = await this.installLocal([...localInput.files]);
+					await this.bindings.install_dependencies(spec);
+					this.packagesChanged();
+					this.showStatus(`Installed ${packageName(spec)}`);
+				} catch (error) {
+					this.showError(error);
+				}
+				localInput.value = "";
+			});
+		}
+	}
+
+	enablePackageRemoval() {
+		let button = document.getElementById("remove-unused-button");
+		button.addEventListener("click", (_) => {
+			let used = this.typst.imported_packages();
+			let freed = this.bindings.remove_unused_packages(used);
+			this.showStatus(`Removed unused packages, freed ${formatBytes(freed)}`);
+			this.packagesChanged();
+		});
+	}
+
+	showStatus(text) {
+		this.showError(null);
+		document.getElementById("package-status").textContent = text;
+	}
+
+	// Installation errors are `WasmInstallError`s with a `kind` and a `message`
+	showError(error) {
+		let element = document.getElementById("package-error");
+		element.textContent = error == null ? "" : error.message ?? String(error);
+		element.hidden = error == null;
+		if (error != null) {
+			document.getElementById("package-status").textContent = "";
+			console.log(error);
+		}
+	}
+
+	// Either a single .tar.gz archive, the package files or a package directory including the
+	// typst.toml
+	async installLocal(files) {
+		if (files.length === 1 && /\.(tar\.gz|tgz)$/.test(files[0].name)) {
+			return this.bindings.install_local_archive(await files[0].arrayBuffer());
+		}
+		let contents = {};
+		for (let file of files) {
+			contents[file.webkitRelativePath || file.name] = await file.arrayBuffer();
+		}
+		return this.bindings.install_local_files(contents);
 	}
 }
 
 class App {
 	constructor() {
+		// Increased for every compilation, which is not a retry after installing packages
+		this.generation = 0;
+		this.failedPackages = new Map();
+		// The compile worker, `null` if the page compiles itself
+		this.worker = null;
+		// The pending requests to the worker by their id
+		this.requests = new Map();
+		this.nextRequestId = 0;
+		this.pdfUrl = null;
 		this.init();
 	}
 
@@ -106,17 +245,77 @@ class App {
 
 	initWasm() {
 		return new Promise((resolve, reject) => {
-			document.addEventListener("wasmload", (event) => {
+			document.addEventListener("wasmload", async (event) => {
 				let bindings = event.detail.bindings;
 				this.bindings = bindings;
-				this.typst = new bindings.SystemWorld();
-				this.packageManager = new PackageManager(bindings);
+				let storage = await this.openStorage(bindings);
+				this.typst = bindings.SystemWorld.with_storage(storage);
+				this.packageManager = new PackageManager(bindings, storage, this.typst, () =>
+					this.send("reload"),
+				);
+				// The worker only sees the packages, which are stored in IndexedDB
+				if (this.sharedStorage) this.startWorker(event.detail.urls);
 				this.loadingStepDone("typst");
 				resolve();
 			});
 		});
 	}
 
+	// Compiles in the background, such that typing is not blocked by the compiler.
+	// See `src/worker.rs` for the messages.
+	startWorker(urls) {
+		this.worker = new Worker("worker.js", { type: "module" });
+		this.worker.addEventListener("message", (event) => this.onWorkerMessage(event.data));
+		this.worker.addEventListener("error", (event) => {
+			console.log("Compile worker failed", event);
+			this.notify("error", "Build failed", event.message ?? "The compiler could not be started");
+		});
+		this.worker.postMessage(urls);
+	}
+
+	// Sends a request, whose response is not needed, does nothing without a worker
+	send(kind, fields = {}) {
+		this.worker?.postMessage({ version: 1, id: null, kind, ...fields });
+	}
+
+	// Resolves with the response to the request
+	request(kind, fields = {}) {
+		let id = this.nextRequestId++;
+		return new Promise((resolve) => {
+			this.requests.set(id, resolve);
+			this.worker.postMessage({ version: 1, id, kind, ...fields });
+		});
+	}
+
+	onWorkerMessage(message) {
+		let resolve = this.requests.get(message.id);
+		if (resolve != null) {
+			this.requests.delete(message.id);
+			resolve(message);
+		} else if (message.kind === "error") {
+			console.log("Compile worker error", message.message);
+		}
+	}
+
+	async openStorage(bindings) {
+		try {
+			let storage = await bindings.StorageBackend.indexed_db("typst_live");
+			await storage.prefetch("");
+			try {
+				// Packages used to be stored in localStorage, where they use up its small quota.
+				await storage.move_from(bindings.StorageBackend.local(), "packages/");
+			} catch (error) {
+				console.log("Could not move packages from localStorage", error);
+			}
+			this.sharedStorage = true;
+			return storage;
+		} catch (error) {
+			console.log("IndexedDB is not available, using localStorage", error);
+			this.sharedStorage = false;
+			return bindings.StorageBackend.local();
+		}
+	}
+
 	initDialogs() {
 		this.dialogs = [];
 		window.addEventListener("keydown", (e) => {
@@ -165,7 +364,11 @@ class App {
 	}
 
 	initFonts(fontBuffers) {
-		this.typst.add_fonts(fontBuffers);
+		if (this.worker != null) {
+			this.send("add_fonts", { fonts: fontBuffers });
+		} else {
+			this.typst.add_fonts(fontBuffers);
+		}
 		let code = document.getElementById("code").value;
 		if (code != null && code != "") this.recompile(code);
 	}
@@ -200,6 +403,71 @@ class App {
 
 	initCodePost() {
 		this.loadFromURL();
+		// The worker sends the lock file with every compiled document
+		this.lockFile = this.typst.lock_file();
+		this.initPreviewSync();
+	}
+
+	// Only the SVG preview can be synced, the PDF plugin does not expose clicks
+	initPreviewSync() {
+		let textarea = document.getElementById("code");
+		let pages = document.getElementById("svg-pages");
+		pages.addEventListener("click", async (event) => {
+			let page = event.target.closest("svg");
+			if (page == null) return;
+			let rect = page.getBoundingClientRect();
+			let scale = page.viewBox.baseVal.width / rect.width;
+			let jump = await this.jumpFromClick(
+				[...pages.children].indexOf(page) + 1,
+				(event.clientX - rect.left) * scale,
+				(event.clientY - rect.top) * scale,
+			);
+			if (jump == null) return;
+			if (jump.kind === "source" && jump.path === "/main.typ" && jump.package == null) {
+				textarea.focus();
+				textarea.setSelectionRange(jump.offset_utf16, jump.offset_utf16);
+			} else if (jump.kind === "url") {
+				window.open(jump.url, "_blank");
+			} else if (jump.kind === "position") {
+				this.scrollPreviewTo(jump.position);
+			}
+		});
+		let onCursorMove = async () => {
+			if (!this.svgPreview) return;
+			let prefix = textarea.value.slice(0, textarea.selectionStart);
+			let cursor = new TextEncoder().encode(prefix).length;
+			try {
+				let position = await this.jumpFromCursor("/main.typ", cursor);
+				if (position != null) this.scrollPreviewTo(position);
+			} catch (error) {
+				console.log(error);
+			}
+		};
+		textarea.addEventListener("click", onCursorMove);
+		textarea.addEventListener("keyup", onCursorMove);
+	}
+
+	async jumpFromClick(page, x, y) {
+		if (this.worker == null) return this.typst.jump_from_click(page, x, y);
+		return (await this.request("jump_from_click", { page, x, y })).jump;
+	}
+
+	async jumpFromCursor(path, cursor) {
+		if (this.worker == null) return this.typst.jump_from_cursor(path, cursor);
+		let response = await this.request("jump_from_cursor", { path, cursor });
+		if (response.kind === "error") throw new Error(response.message);
+		return response.position;
+	}
+
+	scrollPreviewTo(position) {
+		let pages = document.getElementById("svg-pages");
+		let page = pages.children[position.page - 1];
+		if (page == null) return;
+		let scale = page.getBoundingClientRect().height / page.viewBox.baseVal.height;
+		pages.scrollTo({
+			top: page.offsetTop - pages.offsetTop + position.y * scale - pages.clientHeight / 2,
+			behavior: "smooth",
+		});
 	}
 
 	initSettingsPost() {
@@ -208,6 +476,37 @@ class App {
 		toggle.addEventListener("change", () => {
 			this.setCompileOnWrite(!toggle.checked);
 		});
+		let installToggle = document.getElementById("install-toggle");
+		let autoInstall = localStorage.getItem(AUTO_INSTALL_KEY);
+		if (autoInstall != null) installToggle.checked = JSON.parse(autoInstall);
+		this.autoInstall = installToggle.checked;
+		installToggle.addEventListener("change", () => {
+			this.autoInstall = installToggle.checked;
+			localStorage.setItem(AUTO_INSTALL_KEY, JSON.stringify(this.autoInstall));
+			this.failedPackages.clear();
+		});
+		let registryInput = document.getElementById("registry-input");
+		let saved = localStorage.getItem(REGISTRIES_KEY);
+		if (saved != null) this.packageManager.registries = JSON.parse(saved);
+		registryInput.value = this.packageManager.registries.join(" ");
+		registryInput.addEventListener("change", () => {
+			this.packageManager.registries = registryInput.value.split(/\s+/).filter((url) => url !== "");
+			registryInput.value = this.packageManager.registries.join(" ");
+			localStorage.setItem(REGISTRIES_KEY, JSON.stringify(this.packageManager.registries));
+			this.failedPackages.clear();
+		});
+		let previewToggle = document.getElementById("preview-toggle");
+		this.setSvgPreview(previewToggle.checked);
+		previewToggle.addEventListener("change", () => {
+			this.setSvgPreview(previewToggle.checked);
+			this.onCodeChange();
+		});
+	}
+
+	setSvgPreview(enable) {
+		this.svgPreview = enable;
+		document.getElementById("pdf").hidden = enable;
+		document.getElementById("svg-pages").hidden = !enable;
 	}
 
 	initSplit() {
@@ -223,23 +522,38 @@ class App {
 	}
 	onCodeChange() {
 		let code = document.getElementById("code").value;
-		let encoded_code = this.bindings.encode_string_into_url(code);
-		if (encoded_code != null)
-			window.history.replaceState(
-				window.history.state,
-				"",
-				"/?text=" + encoded_code,
-			);
+		this.saveToURL(code);
 		this.recompile(code);
 	}
+	// The lock file is shared as well, such that the same package files are used everywhere
+	saveToURL(code) {
+		let encoded_code = this.bindings.encode_string_into_url(code);
+		if (encoded_code == null) return;
+		let url = "/?text=" + encoded_code;
+		let lock = this.worker == null ? this.typst.lock_file() : this.lockFile;
+		let encoded_lock = lock == null ? null : this.bindings.encode_string_into_url(lock);
+		if (encoded_lock != null) url += "&lock=" + encoded_lock;
+		window.history.replaceState(window.history.state, "", url);
+	}
 	loadFromURL() {
-		let path = window.location.search.slice(1);
-		if (path.length <= 5) return;
-		let code = decodeURIComponent(path.slice(5));
-		code = this.bindings.decode_string_from_url(code);
+		let params = new URLSearchParams(window.location.search);
+		let text = params.get("text");
+		if (text == null || text === "") return;
+		let code = this.bindings.decode_string_from_url(text);
 		document.getElementById("code").value = code;
 		if (code == null) {
 			window.location.search = "";
+			return;
+		}
+		let lock = params.get("lock");
+		lock = lock == null ? null : this.bindings.decode_string_from_url(lock);
+		if (lock != null) {
+			this.typst.set_source("/typst.lock", lock);
+			this.send("set_source", { path: "/typst.lock", text: lock });
+		} else if (this.typst.lock_file() != null) {
+			// The stored lock file belongs to another document
+			this.typst.delete_file("/typst.lock");
+			this.send("delete_file", { path: "/typst.lock" });
 		}
 	}
 
@@ -261,32 +575,144 @@ class App {
 			code.addEventListener("keydown", this.onCtrlS);
 		}
 	}
-	recompile(code) {
+	formatDiagnostic(diagnostic) {
+		let text = diagnostic.message;
+		let span = diagnostic.span;
+		if (span != null) {
+			text = `${span.path}:${span.start_line + 1}:${span.start_column + 1}: ${text}`;
+		}
+		diagnostic.hints.forEach((hint) => (text += `\nhint: ${hint}`));
+		return text;
+	}
+
+	// `installed` holds the packages, which were installed for this compilation already
+	async recompile(code, installed = new Set(), generation = ++this.generation) {
+		if (this.worker == null) return this.recompileOnMainThread(code, installed, generation);
+		// The page still needs the main source for the imported packages
+		this.typst.set_source("/main.typ", code);
+		this.send("set_source", { path: "/main.typ", text: code });
+		let format = this.svgPreview ? "svg" : "pdf";
+		let response = await this.request("compile", { format });
+		if (response.kind === "compiled") {
+			this.showOutput(format, response.output);
+			this.showDiagnostics(response.warnings);
+			// Compiling may have added packages to the lock file
+			this.lockFile = response.lock_file;
+			this.saveToURL(code);
+		} else if (response.kind === "failed") {
+			if (await this.installMissingPackages(response.missing_packages, installed)) {
+				// A newer compilation started during the download and already shows newer code
+				if (generation !== this.generation) return;
+				await this.request("reload");
+				return this.recompile(code, installed, generation);
+			}
+			this.showDiagnostics(response.diagnostics);
+		} else if (response.kind === "error") {
+			this.notify("error", "Build failed", response.message);
+		}
+		// Cancelled compilations were superseded by a newer one, which shows its own result
+	}
+
+	showOutput(format, output) {
+		if (format === "svg") {
+			document.getElementById("svg-pages").innerHTML = output.join("");
+		} else {
+			if (this.pdfUrl != null) URL.revokeObjectURL(this.pdfUrl);
+			this.pdfUrl = URL.createObjectURL(new Blob([output], { type: "application/pdf" }));
+			document.getElementById("pdf").src = this.pdfUrl;
+		}
+	}
+
+	// Used, if there is no worker, because the storage cannot be shared with it
+	async recompileOnMainThread(code, installed, generation) {
 		try {
-			let result = this.typst.compile_to_pdf(code);
-			document.getElementById("pdf").src = result;
+			await this.typst.prefetch();
+			let result;
+			if (this.svgPreview) {
+				result = this.typst.compile_to_svg(code);
+				document.getElementById("svg-pages").innerHTML = result.output.join("");
+			} else {
+				result = this.typst.compile_to_pdf(code);
+				document.getElementById("pdf").src = result.output;
+			}
+			this.showDiagnostics(result.warnings);
+			// Compiling may have added packages to the lock file
+			this.saveToURL(code);
 		} catch (errors) {
-			errors.forEach(
-				(error) =>
-					new Notify({
-						status: "error",
-						title: "Build failed",
-						text: error,
-						effect: "fade",
-						speed: 300,
-						showIcon: true,
-						showCloseButton: true,
-						autoclose: true,
-						autotimeout: 5000,
-						gap: 20,
-						distance: 20,
-						type: 1,
-						position: "right top",
-					}),
-			);
+			let missing = this.typst.missing_packages().map(packageName);
+			if (await this.installMissingPackages(missing, installed)) {
+				// A newer compilation started during the download and already shows newer code
+				if (generation !== this.generation) return;
+				return this.recompileOnMainThread(code, installed, generation);
+			}
+			// Failures outside of the compiler, e.g. of the storage, are no diagnostics
+			if (Array.isArray(errors)) {
+				this.showDiagnostics(errors);
+			} else {
+				this.notify("error", "Build failed", errors.message ?? String(errors));
+			}
 			console.log(errors);
 		}
 	}
+
+	// `missing` holds the packages as `@namespace/name:version`.
+	// Returns whether packages were installed and compiling again may succeed
+	async installMissingPackages(missing, installed) {
+		if (!this.autoInstall) return false;
+		let now = Date.now();
+		missing = missing.filter((name) => {
+			let failed = this.failedPackages.get(name);
+			return (failed == null || now - failed > INSTALL_RETRY_TIMEOUT) && !installed.has(name);
+		});
+		if (missing.length === 0) return false;
+		let results = await Promise.allSettled(
+			missing.map((name) => this.packageManager.bindings.download_package_from_str(name)),
+		);
+		let success = false;
+		results.forEach((result, i) => {
+			let name = missing[i];
+			if (result.status === "fulfilled") {
+				installed.add(name);
+				this.failedPackages.delete(name);
+				success = true;
+				this.notify("success", "Package installed", name);
+			} else {
+				this.failedPackages.set(name, Date.now());
+				let reason = result.reason.message ?? result.reason;
+				this.notify("error", "Package installation failed", `${name}: ${reason}`);
+			}
+		});
+		return success;
+	}
+
+
+	showDiagnostics(diagnostics) {
+		diagnostics.forEach((diagnostic) =>
+			this.notify(
+				diagnostic.severity,
+				diagnostic.severity === "error" ? "Build failed" : "Warning",
+				this.formatDiagnostic(diagnostic),
+			),
+		);
+	}
+
+	notify(status, title, text) {
+		new Notify({
+			status: status,
+			title: title,
+			text: text,
+			effect: "fade",
+			speed: 300,
+			showIcon: true,
+			showCloseButton: true,
+			autoclose: true,
+			autotimeout: 5000,
+			gap: 20,
+			distance: 20,
+			type: 1,
+			position: "right top",
+		});
+	}
 }
 
 window.addEventListener("load", (_) => new App());
@@ -0,0 +1,25 @@
+// Entry point of the compile worker, see `src/worker.rs` for the message protocol.
+// Start it with `new Worker("worker.js", { type: "module" })` and send the URLs
+// of the wasm bindings, which are part of the `wasmload` event, as the first message.
+// Requests can be sent right away, they are handled once the worker is ready.
+self.onmessage = async (event) => {
+	let queued = [];
+	self.onmessage = (event) => queued.push(event.data);
+	let urls = event.data;
+	let bindings = await import(urls.js);
+	await bindings.default(urls.wasm);
+	let storage;
+	try {
+		storage = await bindings.StorageBackend.indexed_db("typst_live");
+	} catch (error) {
+		// Web Workers have no localStorage, packages are only kept until the worker is terminated
+		storage = bindings.StorageBackend.memory();
+	}
+	await storage.prefetch("");
+	// Replaces the queueing handler
+	bindings.start_compile_worker(storage);
+	self.postMessage({ version: 1, id: null, kind: "ready" });
+	for (let data of queued) {
+		self.dispatchEvent(new MessageEvent("message", { data }));
+	}
+};
@@ -20,6 +20,16 @@ impl From<Version> for WasmVersion {
     }
 }
 
+impl From<WasmVersion> for Version {
+    fn from(value: WasmVersion) -> Self {
+        Self {
+            major: value.major,
+            minor: value.minor,
+            patch: value.patch,
+        }
+    }
+}
+
 #[wasm_bindgen]
 impl WasmVersion {
     #[wasm_bindgen(constructor)]
@@ -58,6 +68,16 @@ impl From<PackageSpec> for WasmPackageSpec {
     }
 }
 
+impl From<&WasmPackageSpec> for PackageSpec {
+    fn from(value: &WasmPackageSpec) -> Self {
+        Self {
+            namespace: value.namespace.as_str().into(),
+            name: value.name.as_str().into(),
+            version: value.version.into(),
+        }
+    }
+}
+
 #[wasm_bindgen]
 impl WasmPackageSpec {
     #[wasm_bindgen(constructor)]
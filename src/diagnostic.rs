@@ -0,0 +1,200 @@
+use js_sys::{Array, Object, Reflect};
+use typst::diag::{Severity, SourceDiagnostic};
+use typst::syntax::Span;
+use typst::World;
+use wasm_bindgen::prelude::*;
+
+/// A location in a source file, resolved from a typst `Span`.
+///
+/// Lines and columns start at zero, columns count characters.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug)]
+pub struct WasmSpan {
+    /// The rooted path of the file inside the project or package.
+    pub path: String,
+    /// The package the file belongs to as `@namespace/name:version`.
+    pub package: Option<String>,
+    pub start: usize,
+    pub end: usize,
+    /// Offsets for JavaScript strings, e.g. `textarea.setSelectionRange`.
+    pub start_utf16: usize,
+    pub end_utf16: usize,
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl WasmSpan {
+    pub fn resolve(world: &dyn World, span: Span) -> Option<Self> {
+        let id = span.id()?;
+        let source = world.source(id).ok()?;
+        let range = source.range(span)?;
+        Some(Self {
+            path: id.vpath().as_rooted_path().to_string_lossy().into_owned(),
+            package: id.package().map(|spec| spec.to_string()),
+            start: range.start,
+            end: range.end,
+            start_utf16: source.byte_to_utf16(range.start)?,
+            end_utf16: source.byte_to_utf16(range.end)?,
+            start_line: source.byte_to_line(range.start)?,
+            start_column: source.byte_to_column(range.start)?,
+            end_line: source.byte_to_line(range.end)?,
+            end_column: source.byte_to_column(range.end)?,
+        })
+    }
+}
+
+impl WasmSpan {
+    pub fn to_object(&self) -> Object {
+        let object = Object::new();
+        set(&object, "path", &JsValue::from_str(&self.path));
+        set(&object, "package", &self.package.clone().into());
+        set(&object, "start", &number(self.start));
+        set(&object, "end", &number(self.end));
+        set(&object, "start_utf16", &number(self.start_utf16));
+        set(&object, "end_utf16", &number(self.end_utf16));
+        set(&object, "start_line", &number(self.start_line));
+        set(&object, "start_column", &number(self.start_column));
+        set(&object, "end_line", &number(self.end_line));
+        set(&object, "end_column", &number(self.end_column));
+        object
+    }
+}
+
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug)]
+pub struct WasmTracepoint {
+    pub message: String,
+    pub span: Option<WasmSpan>,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct WasmDiagnostic {
+    #[wasm_bindgen(skip)]
+    pub severity: String,
+    #[wasm_bindgen(skip)]
+    pub message: String,
+    #[wasm_bindgen(skip)]
+    pub span: Option<WasmSpan>,
+    #[wasm_bindgen(skip)]
+    pub hints: Vec<String>,
+    #[wasm_bindgen(skip)]
+    pub trace: Vec<WasmTracepoint>,
+}
+
+impl WasmDiagnostic {
+    pub fn new(world: &dyn World, diagnostic: &SourceDiagnostic) -> Self {
+        let severity = match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        Self {
+            severity: severity.to_owned(),
+            message: diagnostic.message.to_string(),
+            span: WasmSpan::resolve(world, diagnostic.span),
+            hints: diagnostic
+                .hints
+                .iter()
+                .map(|hint| hint.to_string())
+                .collect(),
+            trace: diagnostic
+                .trace
+                .iter()
+                .map(|point| WasmTracepoint {
+                    message: point.v.to_string(),
+                    span: WasmSpan::resolve(world, point.span),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl WasmDiagnostic {
+    /// A plain object with the same properties as the exported class.
+    pub fn to_object(&self) -> Object {
+        let object = Object::new();
+        set(&object, "severity", &JsValue::from_str(&self.severity));
+        set(&object, "message", &JsValue::from_str(&self.message));
+        set(&object, "span", &span_object(&self.span));
+        set(&object, "hints", &self.hints());
+        let trace: Array = self
+            .trace
+            .iter()
+            .map(|point| {
+                let object = Object::new();
+                set(&object, "message", &JsValue::from_str(&point.message));
+                set(&object, "span", &span_object(&point.span));
+                JsValue::from(object)
+            })
+            .collect();
+        set(&object, "trace", &trace);
+        object
+    }
+}
+
+fn span_object(span: &Option<WasmSpan>) -> JsValue {
+    span.as_ref()
+        .map_or(JsValue::NULL, |span| span.to_object().into())
+}
+
+fn number(value: usize) -> JsValue {
+    JsValue::from_f64(value as f64)
+}
+
+pub(crate) fn set(object: &Object, key: &str, value: &JsValue) {
+    Reflect::set(object, &JsValue::from_str(key), value).expect("Could not set property");
+}
+
+#[wasm_bindgen]
+impl WasmDiagnostic {
+    /// Either `error` or `warning`.
+    #[wasm_bindgen(getter)]
+    pub fn severity(&self) -> String {
+        self.severity.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+    /// The location of the diagnostic, missing if it does not point into a source file.
+    #[wasm_bindgen(getter)]
+    pub fn span(&self) -> Option<WasmSpan> {
+        self.span.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn hints(&self) -> Array {
+        self.hints
+            .iter()
+            .map(|hint| JsValue::from_str(hint))
+            .collect()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn trace(&self) -> Array {
+        self.trace.iter().cloned().map(JsValue::from).collect()
+    }
+}
+
+/// Resolves the spans of typst's diagnostics.
+pub fn resolve<'a>(
+    world: &dyn World,
+    diagnostics: impl IntoIterator<Item = &'a SourceDiagnostic>,
+) -> Vec<WasmDiagnostic> {
+    diagnostics
+        .into_iter()
+        .map(|diagnostic| WasmDiagnostic::new(world, diagnostic))
+        .collect()
+}
+
+pub fn to_js_array(diagnostics: Vec<WasmDiagnostic>) -> Array {
+    diagnostics.into_iter().map(JsValue::from).collect()
+}
+
+/// Converts diagnostics into plain objects, which can be sent with `postMessage`.
+pub fn to_plain_array(diagnostics: &[WasmDiagnostic]) -> Array {
+    diagnostics
+        .iter()
+        .map(|diagnostic| JsValue::from(diagnostic.to_object()))
+        .collect()
+}
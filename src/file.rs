@@ -1,67 +1,488 @@
 use crate::{MAIN_SOURCE_NAME};
+use js_sys::Promise;
 use once_cell::unsync::OnceCell;
 use siphasher::sip128::{Hasher128, SipHasher13};
 use std::cell::{RefCell, RefMut};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
+use std::future::ready;
 use std::hash::Hash;
+use std::ops::Range;
 
 use std::path::{Path, PathBuf};
 use typst::{
-    diag::{FileError, FileResult},
+    diag::{EcoString, FileError, FileResult, PackageError},
     eval::Bytes,
-    syntax::{FileId, Source, VirtualPath},
+    syntax::{parse, FileId, PackageSpec, Source, VirtualPath},
 };
 
 
 
-use crate::lfs::LFS;
-use crate::package::prepare_package;
+use crate::compat::WasmPackageSpec;
+use crate::lock::{LockFile, PackageHashes, LOCK_FILE_NAME};
+use crate::package::{collect_specs, package_descriptions, prepare_package, verify_package};
+use crate::storage::{FileStorage, StorageFuture};
+
+/// Uploaded project files are stored below this prefix, such that they survive a reload.
+const PROJECT_PREFIX: &str = "project/";
+/// Source files are stored below this prefix, except for the main source, which is kept in the
+/// URL instead.
+const SOURCE_PREFIX: &str = "sources/";
 
 pub struct VFS {
-    main: Option<Source>,
+    storage: Rc<dyn FileStorage>,
     main_id: FileId,
+    /// The project's own source files, which the user edits. Always contains the main source.
+    ///
+    /// They are also kept in the storage, see [`SOURCE_PREFIX`].
+    sources: HashMap<FileId, Source>,
+    /// Binary project files uploaded by the user, e.g. images, data files and bibliographies.
+    ///
+    /// They are also kept in the storage, see [`PROJECT_PREFIX`].
+    assets: HashMap<FileId, Bytes>,
+    /// Project files, which were changed or removed since they were last saved.
+    unsaved: HashSet<FileId>,
     hashes: RefCell<HashMap<FileId, FileResult<PathHash>>>,
     paths: RefCell<HashMap<PathHash, PathSlot>>,
+    /// Packages which were imported, but are not installed.
+    missing_packages: RefCell<Vec<PackageSpec>>,
+    /// The verified packages of the current compilation, with their directories and hashes.
+    packages: RefCell<HashMap<PackageSpec, (PathBuf, PackageHashes)>>,
+    /// The lock file as it was when the current compilation started.
+    lock: RefCell<FileResult<LockFile>>,
+    /// The verification results of the session together with the package markers they belong
+    /// to, a package is only verified again, if it was installed again.
+    verified: RefCell<HashMap<PackageSpec, (Vec<u8>, Result<PackageHashes, String>)>>,
 }
 
 impl VFS {
-    pub fn new() -> Self {
-        let main_path = VirtualPath::new(Path::new(MAIN_SOURCE_NAME));
+    /// Creates a project with an empty main source and the files which were saved before.
+    ///
+    /// Asynchronous storages have to be prefetched first, otherwise saved files are missing.
+    pub fn new(storage: Rc<dyn FileStorage>) -> Self {
+        let main_id = project_file_id(MAIN_SOURCE_NAME);
+        let mut sources: HashMap<FileId, Source> = storage
+            .list()
+            .into_iter()
+            .filter_map(|key| {
+                let id = project_file_id(key.strip_prefix(SOURCE_PREFIX)?);
+                let text = String::from_utf8(storage.get_bytes(&key)?).ok()?;
+                Some((id, Source::new(id, text)))
+            })
+            .collect();
+        sources.insert(main_id, Source::new(main_id, String::new()));
+        let assets = storage
+            .list()
+            .into_iter()
+            .filter_map(|key| {
+                let path = key.strip_prefix(PROJECT_PREFIX)?;
+                let bytes = storage.get_bytes(&key)?;
+                Some((project_file_id(path), Bytes::from(bytes)))
+            })
+            .collect();
         Self {
-            main: None,
-            main_id: FileId::new(None, main_path),
+            storage,
+            main_id,
+            sources,
+            assets,
+            unsaved: HashSet::new(),
             hashes: RefCell::default(),
             paths: RefCell::default(),
+            missing_packages: RefCell::default(),
+            packages: RefCell::default(),
+            lock: RefCell::new(Ok(LockFile::default())),
+            verified: RefCell::default(),
         }
     }
     pub fn source(&self, id: FileId) -> Result<Source, FileError> {
-        if id.vpath().as_rooted_path().to_str().unwrap() == MAIN_SOURCE_NAME {
-            Ok(self.get_main())
+        if let Some(source) = self.sources.get(&id) {
+            Ok(source.clone())
+        } else if let Some(bytes) = self.assets.get(&id) {
+            let text = decode_utf8(bytes.to_vec())?;
+            Ok(Source::new(id, text))
         } else {
-            self.slot(id)?.source()
+            self.slot(id)?.source(&*self.storage)
         }
     }
     pub fn file(&self, id: FileId) -> FileResult<Bytes> {
-        if id.vpath().as_rooted_path().to_str().unwrap() == MAIN_SOURCE_NAME {
-            let main_source = self.main.as_ref().unwrap();
-            let main_text = main_source.text();
-            let main_bytes = main_text.as_bytes();
-            Ok(Bytes::from(main_bytes))
+        if let Some(source) = self.sources.get(&id) {
+            Ok(Bytes::from(source.text().as_bytes()))
+        } else if let Some(bytes) = self.assets.get(&id) {
+            Ok(bytes.clone())
         } else {
-            self.slot(id)?.file()
+            self.slot(id)?.file(&*self.storage)
         }
     }
 
+    /// Loads every stored file into memory before the next compilation.
+    pub fn prefetch(&self) -> Promise {
+        self.storage.prefetch("")
+    }
+
+    /// Replaces the text of the main source, only the changed part is reparsed.
     pub fn set_main(&mut self, source: String) {
-        self.main = Some(Source::new(self.main_id, source));
+        self.sources
+            .get_mut(&self.main_id)
+            .expect("The main source always exists")
+            .replace(&source);
     }
 
     pub fn get_main(&self) -> Source {
-        self.main.clone().expect("No main was set!")
+        self.sources[&self.main_id].clone()
+    }
+
+    /// The rooted paths of all project files.
+    pub fn list_files(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self
+            .sources
+            .keys()
+            .chain(self.assets.keys())
+            .map(|id| id.vpath().as_rooted_path().to_string_lossy().into_owned())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    pub fn create_file(&mut self, path: &str, text: String) -> FileResult<()> {
+        let id = project_file_id(path);
+        if self.contains(id) {
+            return Err(already_exists(id));
+        }
+        self.sources.insert(id, Source::new(id, text));
+        self.changed(id);
+        Ok(())
+    }
+
+    /// Creates a source file or replaces the text of an existing one.
+    pub fn set_source(&mut self, path: &str, text: String) {
+        let id = project_file_id(path);
+        self.remove_asset(id);
+        match self.sources.get_mut(&id) {
+            Some(source) => {
+                source.replace(&text);
+            }
+            None => {
+                self.sources.insert(id, Source::new(id, text));
+                self.invalidate(id);
+            }
+        }
+        self.changed(id);
+    }
+
+    /// Stores a binary file in the project, replacing an existing file at the same path.
+    pub fn upload_file(&mut self, path: &str, bytes: Bytes) -> FileResult<()> {
+        let id = project_file_id(path);
+        if id == self.main_id {
+            return Err(FileError::Other(Some(
+                "the main file cannot be uploaded".into(),
+            )));
+        }
+        self.sources.remove(&id);
+        self.assets.insert(id, bytes);
+        self.changed(id);
+        self.invalidate(id);
+        Ok(())
+    }
+
+    pub fn update_file(&mut self, path: &str, text: &str) -> FileResult<()> {
+        let id = project_file_id(path);
+        let source = self.sources.get_mut(&id).ok_or_else(|| not_found(id))?;
+        source.replace(text);
+        self.changed(id);
+        Ok(())
+    }
+
+    /// Replaces a byte range of a source file and incrementally reparses it.
+    ///
+    /// Returns the range of the source which was reparsed.
+    pub fn edit_file(
+        &mut self,
+        path: &str,
+        replace: Range<usize>,
+        with: &str,
+    ) -> FileResult<Range<usize>> {
+        let id = project_file_id(path);
+        let source = self.sources.get_mut(&id).ok_or_else(|| not_found(id))?;
+        let text = source.text();
+        if replace.start > replace.end
+            || replace.end > text.len()
+            || !text.is_char_boundary(replace.start)
+            || !text.is_char_boundary(replace.end)
+        {
+            return Err(invalid_range(replace));
+        }
+        let reparsed = source.edit(replace, with);
+        self.changed(id);
+        Ok(reparsed)
+    }
+
+    /// Like [`VFS::edit_file`], but the range is given in UTF-16 code units, as used by JavaScript.
+    pub fn edit_file_utf16(
+        &mut self,
+        path: &str,
+        replace: Range<usize>,
+        with: &str,
+    ) -> FileResult<Range<usize>> {
+        let id = project_file_id(path);
+        let source = self.sources.get(&id).ok_or_else(|| not_found(id))?;
+        let start = source.utf16_to_byte(replace.start);
+        let end = source.utf16_to_byte(replace.end);
+        match (start, end) {
+            (Some(start), Some(end)) => self.edit_file(path, start..end, with),
+            _ => Err(invalid_range(replace)),
+        }
+    }
+
+    pub fn rename_file(&mut self, from: &str, to: &str) -> FileResult<()> {
+        let from_id = project_file_id(from);
+        let to_id = project_file_id(to);
+        if from_id == self.main_id {
+            return Err(FileError::Other(Some(
+                "the main file cannot be renamed".into(),
+            )));
+        }
+        if self.contains(to_id) {
+            return Err(already_exists(to_id));
+        }
+        if let Some(bytes) = self.remove_asset(from_id) {
+            self.assets.insert(to_id, bytes);
+        } else {
+            let source = self
+                .sources
+                .remove(&from_id)
+                .ok_or_else(|| not_found(from_id))?;
+            self.sources
+                .insert(to_id, Source::new(to_id, source.text().to_owned()));
+            self.changed(from_id);
+        }
+        self.changed(to_id);
+        self.invalidate(from_id);
+        self.invalidate(to_id);
+        Ok(())
+    }
+
+    pub fn delete_file(&mut self, path: &str) -> FileResult<()> {
+        let id = project_file_id(path);
+        if id == self.main_id {
+            return Err(FileError::Other(Some(
+                "the main file cannot be deleted".into(),
+            )));
+        }
+        if self.sources.remove(&id).is_none() && self.remove_asset(id).is_none() {
+            return Err(not_found(id));
+        }
+        self.changed(id);
+        self.invalidate(id);
+        Ok(())
+    }
+
+    /// The packages which were not installed, since the last call to `reset_packages`.
+    pub fn missing_packages(&self) -> Vec<PackageSpec> {
+        self.missing_packages.borrow().clone()
+    }
+
+    /// The installed packages with the descriptions from their manifests.
+    pub fn installed_packages(&self) -> Vec<(PackageSpec, Option<EcoString>)> {
+        package_descriptions(&*self.storage)
+    }
+
+    /// The packages, which the project's sources and uploaded Typst files import or include
+    /// directly.
+    pub fn imported_packages(&self) -> Vec<PackageSpec> {
+        let mut specs = vec![];
+        for source in self.sources.values() {
+            collect_specs(source.root(), &mut specs);
+        }
+        let uploaded = self
+            .assets
+            .iter()
+            .filter(|(id, _)| id.vpath().as_rootless_path().extension() == Some("typ".as_ref()));
+        for (_, bytes) in uploaded {
+            if let Ok(text) = std::str::from_utf8(bytes) {
+                collect_specs(&parse(text), &mut specs);
+            }
+        }
+        specs
+    }
+
+    /// Forgets the missing and used packages and reads the lock file again, has to be called
+    /// before every compilation.
+    pub fn reset_packages(&self) {
+        self.missing_packages.borrow_mut().clear();
+        self.packages.borrow_mut().clear();
+        *self.lock.borrow_mut() = self.lock_file();
+    }
+
+    /// The text of the lock file, if the project has one.
+    pub fn lock_file_text(&self) -> Option<String> {
+        let id = project_file_id(LOCK_FILE_NAME);
+        if !self.contains(id) {
+            return None;
+        }
+        self.source(id).ok().map(|source| source.text().to_owned())
+    }
+
+    /// The lock file of the project, which is empty if the project has none.
+    fn lock_file(&self) -> FileResult<LockFile> {
+        let id = project_file_id(LOCK_FILE_NAME);
+        if !self.contains(id) {
+            return Ok(LockFile::default());
+        }
+        LockFile::parse(self.source(id)?.text()).map_err(package_error)
+    }
+
+    /// Adds the packages, which were used since the last call to `reset_packages`, to the lock
+    /// file and removes the unused ones.
+    ///
+    /// The hashes of packages, which are already locked, are never changed. The lock file is
+    /// saved like an uploaded file, such that it survives a reload.
+    pub fn update_lock_file(&mut self) -> FileResult<()> {
+        let lock_id = project_file_id(LOCK_FILE_NAME);
+        if self.packages.borrow().is_empty() && !self.contains(lock_id) {
+            return Ok(());
+        }
+        let mut lock = self.lock_file()?;
+        let packages: HashMap<String, PackageHashes> = self
+            .packages
+            .borrow()
+            .iter()
+            .map(|(spec, (_, hashes))| (spec.to_string(), hashes.clone()))
+            .collect();
+        lock.packages.retain(|spec, _| packages.contains_key(spec));
+        for (spec, hashes) in packages {
+            lock.packages.entry(spec).or_insert(hashes);
+        }
+        let text = lock.to_text();
+        if self.assets.get(&lock_id).map(|bytes| &bytes[..]) != Some(text.as_bytes()) {
+            self.upload_file(LOCK_FILE_NAME, Bytes::from(text.into_bytes()))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the project files, which changed since the last save, to the storage and deletes
+    /// the removed ones from it.
+    ///
+    /// The returned future fails, if the storage refused the write, e.g. because it is full.
+    pub fn save(&mut self) -> StorageFuture<'static> {
+        let mut entries = vec![];
+        for id in self.unsaved.drain() {
+            let (source_key, project_key) = (source_key(id), project_key(id));
+            // A file, which was replaced by a file of the other kind, is stored only once.
+            if let Some(source) = self.sources.get(&id) {
+                entries.push((source_key, source.text().as_bytes().to_vec()));
+                self.storage.delete(&project_key);
+            } else if let Some(bytes) = self.assets.get(&id) {
+                entries.push((project_key, bytes.to_vec()));
+                self.storage.delete(&source_key);
+            } else {
+                self.storage.delete(&source_key);
+                self.storage.delete(&project_key);
+            }
+        }
+        if entries.is_empty() {
+            return Box::pin(ready(Ok(())));
+        }
+        let storage = self.storage.clone();
+        Box::pin(async move { storage.set_all(entries).await })
+    }
+
+    /// Removes an uploaded file from the project, it is deleted from the storage on the next save.
+    fn remove_asset(&mut self, id: FileId) -> Option<Bytes> {
+        let bytes = self.assets.remove(&id)?;
+        self.changed(id);
+        Some(bytes)
+    }
+
+    /// Remembers to save a project file, the main source is kept in the URL instead.
+    fn changed(&mut self, id: FileId) {
+        if id != self.main_id {
+            self.unsaved.insert(id);
+        }
+    }
+
+    fn contains(&self, id: FileId) -> bool {
+        self.sources.contains_key(&id) || self.assets.contains_key(&id)
+    }
+
+    /// Forgets the cached slot of a file, such that it is read again on the next compilation.
+    fn invalidate(&self, id: FileId) {
+        if let Some(Ok(hash)) = self.hashes.borrow_mut().remove(&id) {
+            self.paths.borrow_mut().remove(&hash);
+        }
+    }
+
+    /// Forgets the cached slots of a package's files, such that they are read again.
+    fn invalidate_package(&self, spec: &PackageSpec) {
+        let mut paths = self.paths.borrow_mut();
+        self.hashes.borrow_mut().retain(|id, hash| {
+            if id.package() != Some(spec) {
+                return true;
+            }
+            if let Ok(hash) = hash {
+                paths.remove(hash);
+            }
+            false
+        });
+    }
+
+    /// The directory of an installed package, missing packages are remembered.
+    ///
+    /// The package is checked against the lock file once per compilation.
+    fn package_root(&self, spec: &PackageSpec) -> FileResult<PathBuf> {
+        if let Some((root, _)) = self.packages.borrow().get(spec) {
+            return Ok(root.clone());
+        }
+        let root = prepare_package(&*self.storage, spec).map_err(|error| {
+            if let PackageError::NotFound(spec) = &error {
+                let mut missing = self.missing_packages.borrow_mut();
+                if !missing.contains(spec) {
+                    missing.push(spec.clone());
+                }
+            }
+            FileError::from(error)
+        })?;
+        let hashes = self.verify_package(spec).map_err(package_error)?;
+        if let Some(locked) = self.lock.borrow().clone()?.packages.get(&spec.to_string()) {
+            if !locked.matches(&hashes) {
+                return Err(package_error(format!(
+                    "{} does not match the hashes in {}",
+                    spec, LOCK_FILE_NAME
+                )));
+            }
+        }
+        self.packages
+            .borrow_mut()
+            .insert(spec.clone(), (root.clone(), hashes));
+        Ok(root)
+    }
+
+    /// Verifies an installed package against the hashes, which were recorded on install.
+    ///
+    /// The result is cached until the package is installed again, which changes its marker. The
+    /// package's cached files are dropped then as well.
+    fn verify_package(&self, spec: &PackageSpec) -> Result<PackageHashes, String> {
+        let marker_key = WasmPackageSpec::from(spec.clone()).package_directory_key();
+        let marker = self.storage.get_bytes(&marker_key).unwrap_or_default();
+        if let Some((verified, result)) = self.verified.borrow().get(spec) {
+            if *verified == marker {
+                return result.clone();
+            }
+        }
+        // The files of a previous installation may still be cached.
+        self.invalidate_package(spec);
+        let result = verify_package(&*self.storage, spec);
+        self.verified
+            .borrow_mut()
+            .insert(spec.clone(), (marker, result.clone()));
+        result
     }
 
     fn slot(&self, id: FileId) -> FileResult<RefMut<PathSlot>> {
+        // Packages are checked in every compilation, even if their files are cached.
+        if let Some(spec) = id.package() {
+            self.package_root(spec)?;
+        }
         let mut system_path = PathBuf::new();
         let error_hash = self
             .hashes
@@ -70,11 +491,11 @@ impl VFS {
             .map_or(false, |value| value.is_err());
         if error_hash {
             let root = match id.package() {
-                Some(spec) => prepare_package(spec)?,
+                Some(spec) => self.package_root(spec)?,
                 None => Path::new("/").to_owned(),
             };
-            system_path = root.join(id.vpath().as_rooted_path());
-            let hash = PathHash::new(&system_path);
+            system_path = id.vpath().resolve(&root).ok_or(FileError::AccessDenied)?;
+            let hash = PathHash::new(&*self.storage, &system_path);
 
             self.hashes.borrow_mut().insert(id, hash);
         }
@@ -86,14 +507,14 @@ impl VFS {
                 // Determine the root path relative to which the file path
                 // will be resolved.
                 let root = match id.package() {
-                    Some(spec) => prepare_package(spec)?,
+                    Some(spec) => self.package_root(spec)?,
                     None => Path::new("/").to_owned(),
                 };
 
                 // Join the path to the root. If it tries to escape, deny
                 // access. Note: It can still escape via symlinks.
-                system_path = root.join(id.vpath().as_rooted_path());
-                PathHash::new(&system_path)
+                system_path = id.vpath().resolve(&root).ok_or(FileError::AccessDenied)?;
+                PathHash::new(&*self.storage, &system_path)
             })
             .clone()?;
 
@@ -122,25 +543,25 @@ struct PathSlot {
 }
 
 impl PathSlot {
-    fn source(&mut self) -> FileResult<Source> {
+    fn source(&mut self, storage: &dyn FileStorage) -> FileResult<Source> {
         if let Some(Err(_)) = self.source.get() {
             self.source = OnceCell::new();
         }
         self.source
             .get_or_init(|| {
-                let buf = read(&self.system_path)?;
+                let buf = read(storage, &self.system_path)?;
                 let text = decode_utf8(buf)?;
                 Ok(Source::new(self.id, text))
             })
             .clone()
     }
 
-    fn file(&mut self) -> FileResult<Bytes> {
+    fn file(&mut self, storage: &dyn FileStorage) -> FileResult<Bytes> {
         if let Some(Err(_)) = self.buffer.get() {
             self.buffer = OnceCell::new();
         }
         self.buffer
-            .get_or_init(|| read(&self.system_path).map(Bytes::from))
+            .get_or_init(|| read(storage, &self.system_path).map(Bytes::from))
             .clone()
     }
 }
@@ -150,27 +571,70 @@ impl PathSlot {
 struct PathHash(u128);
 
 impl PathHash {
-    fn new(path: &Path) -> FileResult<Self> {
+    fn new(storage: &dyn FileStorage, path: &Path) -> FileResult<Self> {
         let _f = |e| FileError::from_io(e, path);
         let mut state = SipHasher13::new();
         path.to_str().unwrap().hash(&mut state);
-        let content = read(path)?;
+        let content = read(storage, path)?;
         content.hash(&mut state);
         Ok(Self(state.finish128().as_u128()))
     }
 }
 
+/// The id of a file in the project, paths are resolved relative to the project root.
+pub fn project_file_id(path: &str) -> FileId {
+    FileId::new(None, VirtualPath::new(Path::new(path)))
+}
+
+/// The storage key of an uploaded project file.
+fn project_key(id: FileId) -> String {
+    format!(
+        "{}{}",
+        PROJECT_PREFIX,
+        id.vpath().as_rootless_path().to_string_lossy()
+    )
+}
+
+/// The storage key of a source file.
+fn source_key(id: FileId) -> String {
+    format!(
+        "{}{}",
+        SOURCE_PREFIX,
+        id.vpath().as_rootless_path().to_string_lossy()
+    )
+}
+
+fn not_found(id: FileId) -> FileError {
+    FileError::NotFound(id.vpath().as_rooted_path().to_owned())
+}
+
+fn invalid_range(range: Range<usize>) -> FileError {
+    FileError::Other(Some(
+        format!("invalid range {}..{}", range.start, range.end).into(),
+    ))
+}
+
+fn package_error(message: String) -> FileError {
+    PackageError::Other(Some(message.into())).into()
+}
+
+fn already_exists(id: FileId) -> FileError {
+    FileError::Other(Some(
+        format!("{} already exists", id.vpath().as_rooted_path().display()).into(),
+    ))
+}
+
 /// Read a file.
-fn read(path: &Path) -> FileResult<Vec<u8>> {
-    let lfs = LFS::new();
+fn read(storage: &dyn FileStorage, path: &Path) -> FileResult<Vec<u8>> {
     let key = path.to_str().ok_or(FileError::Other(None))?;
-    if !lfs.exists(key) {
+    if !storage.exists(key) {
         return Err(FileError::NotFound(path.to_owned()));
     }
-    lfs.get_bytes(key)
-        .map_or(FileResult::Err(FileError::Other(None)), |bytes| {
-            FileResult::Ok(bytes)
-        })
+    // Asynchronous storages only return values which were prefetched.
+    storage.get_bytes(key).map_or(
+        FileResult::Err(FileError::Other(Some("file was not loaded yet".into()))),
+        |bytes| FileResult::Ok(bytes),
+    )
 }
 
 /// Decode UTF-8 with an optional BOM.
@@ -183,3 +647,34 @@ fn decode_utf8(buf: Vec<u8>) -> FileResult<String> {
         String::from_utf8(buf)?
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn vfs_with(text: &str) -> VFS {
+        let mut vfs = VFS::new(Rc::new(MemoryStorage::new()));
+        vfs.create_file("/chapter.typ", text.to_owned()).unwrap();
+        vfs
+    }
+
+    #[test]
+    fn edit_file_rejects_invalid_ranges() {
+        let mut vfs = vfs_with("héllo");
+        let (start, end) = (3, 1);
+        assert!(vfs.edit_file("/chapter.typ", start..end, "").is_err());
+        assert!(vfs.edit_file("/chapter.typ", 0..7, "").is_err());
+        // Inside of the two bytes of `é`.
+        assert!(vfs.edit_file("/chapter.typ", 2..3, "").is_err());
+        assert!(vfs.edit_file("/missing.typ", 0..0, "").is_err());
+    }
+
+    #[test]
+    fn edit_file_replaces_range() {
+        let mut vfs = vfs_with("héllo");
+        vfs.edit_file("/chapter.typ", 1..3, "e").unwrap();
+        let source = vfs.source(project_file_id("/chapter.typ")).unwrap();
+        assert_eq!(source.text(), "hello");
+    }
+}
@@ -0,0 +1,66 @@
+use std::ops::Range;
+
+use js_sys::Array;
+use typst::syntax::{highlight, highlight_html, LinkedNode, Tag};
+use wasm_bindgen::prelude::*;
+
+use crate::file::project_file_id;
+use crate::{file_error, SystemWorld};
+
+/// Collects the highlighted nodes, which intersect with `range`, in document order.
+///
+/// Nodes may be nested, e.g. a heading contains the highlighted text of its body.
+fn collect(node: &LinkedNode, range: &Range<usize>, tokens: &mut Vec<u32>) {
+    let node_range = node.range();
+    if node_range.end < range.start || node_range.start > range.end {
+        return;
+    }
+    if let Some(tag) = highlight(node) {
+        let index = Tag::LIST
+            .iter()
+            .position(|&other| other == tag)
+            .expect("Every tag is listed");
+        tokens.extend([node_range.start as u32, node_range.end as u32, index as u32]);
+    }
+    for child in node.children() {
+        collect(&child, range, tokens);
+    }
+}
+
+#[wasm_bindgen]
+impl SystemWorld {
+    /// The CSS classes of the highlighting tags, indexed like the tags in `highlight`.
+    pub fn highlight_tags() -> Array {
+        Tag::LIST
+            .iter()
+            .map(|tag| JsValue::from_str(tag.css_class()))
+            .collect()
+    }
+
+    /// Highlights a project file, returns `[start, end, tag]` triples as a flat array.
+    ///
+    /// `start` and `end` are byte offsets, `tag` is an index into `highlight_tags`.
+    pub fn highlight(&self, path: &str) -> Result<Vec<u32>, JsValue> {
+        self.highlight_range(path, 0, usize::MAX)
+    }
+
+    /// Like `highlight`, but only returns the tokens which intersect with `start..end`, e.g. the
+    /// range which was reparsed after an `edit`.
+    pub fn highlight_range(
+        &self,
+        path: &str,
+        start: usize,
+        end: usize,
+    ) -> Result<Vec<u32>, JsValue> {
+        let source = self.vfs.source(project_file_id(path)).map_err(file_error)?;
+        let mut tokens = vec![];
+        collect(&LinkedNode::new(source.root()), &(start..end), &mut tokens);
+        Ok(tokens)
+    }
+
+    /// Highlights a project file as HTML, which uses the CSS classes of `highlight_tags`.
+    pub fn highlight_html(&self, path: &str) -> Result<String, JsValue> {
+        let source = self.vfs.source(project_file_id(path)).map_err(file_error)?;
+        Ok(highlight_html(source.root()))
+    }
+}
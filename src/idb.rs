@@ -0,0 +1,300 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
+
+use js_sys::{Array, Promise, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{future_to_promise, spawn_local, JsFuture};
+use web_sys::{
+    console, Event, IdbDatabase, IdbKeyRange, IdbObjectStore, IdbOpenDbRequest, IdbRequest,
+    IdbTransaction, IdbTransactionMode,
+};
+
+use crate::storage::{indexed_db, FileStorage, StorageError, StorageFuture, StorageResult};
+
+const STORE_NAME: &str = "blobs";
+const DB_VERSION: u32 = 1;
+
+/// A storage backed by IndexedDB, which keeps every value as a raw `Uint8Array`.
+///
+/// IndexedDB only has an asynchronous API, while typst reads files synchronously. Only the keys
+/// are loaded when the storage is opened, values have to be loaded with [`FileStorage::prefetch`]
+/// before they can be read. Writes go to memory immediately and are persisted in the background,
+/// they are undone in memory if IndexedDB aborts them. Use [`FileStorage::set_all`] to find out
+/// whether a write succeeded.
+pub struct IdbStorage {
+    db: IdbDatabase,
+    keys: Rc<RefCell<BTreeSet<String>>>,
+    cache: Rc<RefCell<HashMap<String, Vec<u8>>>>,
+}
+
+impl IdbStorage {
+    pub async fn open(name: &str) -> StorageResult<Self> {
+        let factory = indexed_db().ok_or(StorageError::Unavailable)?;
+        let open_request = factory
+            .open_with_u32(name, DB_VERSION)
+            .map_err(|_| StorageError::Unavailable)?;
+        let upgrade = upgrade_closure(&open_request);
+        open_request.set_onupgradeneeded(Some(upgrade.as_ref().unchecked_ref()));
+        let db: IdbDatabase = request_future(&open_request)
+            .await
+            .map_err(|_| StorageError::Unavailable)?
+            .dyn_into()
+            .map_err(|_| StorageError::Unavailable)?;
+        open_request.set_onupgradeneeded(None);
+
+        let keys = load_keys(&db).await?;
+        Ok(Self {
+            db,
+            keys: Rc::new(RefCell::new(keys)),
+            cache: Rc::default(),
+        })
+    }
+
+    fn store(&self, mode: IdbTransactionMode) -> StorageResult<IdbObjectStore> {
+        object_store(&self.db, mode)
+    }
+}
+
+impl FileStorage for IdbStorage {
+    fn exists(&self, key: &str) -> bool {
+        self.keys.borrow().contains(key)
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        self.get_bytes(key)
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+    }
+
+    fn get_bytes(&self, key: &str) -> Option<Vec<u8>> {
+        self.cache.borrow().get(key).cloned()
+    }
+
+    fn set(&self, key: &str, value: &str) -> StorageResult<()> {
+        self.set_bytes(key, value.as_bytes())
+    }
+
+    fn set_bytes(&self, key: &str, value: &[u8]) -> StorageResult<()> {
+        let array = Uint8Array::from(value);
+        let store = self.store(IdbTransactionMode::Readwrite)?;
+        store
+            .put_with_key(&array, &JsValue::from_str(key))
+            .map_err(|_| StorageError::Full)?;
+        self.keys.borrow_mut().insert(key.to_owned());
+        self.cache
+            .borrow_mut()
+            .insert(key.to_owned(), value.to_vec());
+
+        let (keys, cache, key) = (self.keys.clone(), self.cache.clone(), key.to_owned());
+        on_abort(&store.transaction(), move || {
+            console::warn_1(&format!("Could not store {}", key).into());
+            keys.borrow_mut().remove(&key);
+            cache.borrow_mut().remove(&key);
+        });
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        if !self.keys.borrow_mut().remove(key) {
+            return false;
+        }
+        let value = self.cache.borrow_mut().remove(key);
+        let Ok(store) = self.store(IdbTransactionMode::Readwrite) else {
+            return false;
+        };
+        if store.delete(&JsValue::from_str(key)).is_err() {
+            return false;
+        }
+
+        let (keys, cache, key) = (self.keys.clone(), self.cache.clone(), key.to_owned());
+        on_abort(&store.transaction(), move || {
+            keys.borrow_mut().insert(key.clone());
+            if let Some(value) = value {
+                cache.borrow_mut().insert(key, value);
+            }
+        });
+        true
+    }
+
+    fn list(&self) -> Vec<String> {
+        self.keys.borrow().iter().cloned().collect()
+    }
+
+    /// Writes the entries in a single transaction and waits until it is committed.
+    fn set_all(&self, entries: Vec<(String, Vec<u8>)>) -> StorageFuture<'_> {
+        Box::pin(async move {
+            let transaction = self
+                .db
+                .transaction_with_str_and_mode(STORE_NAME, IdbTransactionMode::Readwrite)
+                .map_err(|_| StorageError::Unavailable)?;
+            let store = transaction
+                .object_store(STORE_NAME)
+                .map_err(|_| StorageError::Unavailable)?;
+            let committed = transaction_future(&transaction);
+            for (key, value) in &entries {
+                let array = Uint8Array::from(&value[..]);
+                if store.put_with_key(&array, &JsValue::from_str(key)).is_err() {
+                    let _ = transaction.abort();
+                    return Err(StorageError::Full);
+                }
+            }
+            // Quota errors are not thrown by `put`, the transaction is aborted instead.
+            committed.await.map_err(|_| StorageError::Full)?;
+            let mut keys = self.keys.borrow_mut();
+            let mut cache = self.cache.borrow_mut();
+            for (key, value) in entries {
+                keys.insert(key.clone());
+                cache.insert(key, value);
+            }
+            Ok(())
+        })
+    }
+
+    fn prefetch(&self, prefix: &str) -> Promise {
+        let missing = self
+            .keys
+            .borrow()
+            .range(prefix.to_owned()..)
+            .take_while(|key| key.starts_with(prefix))
+            .any(|key| !self.cache.borrow().contains_key(key));
+        if !missing {
+            return Promise::resolve(&JsValue::UNDEFINED);
+        }
+
+        let db = self.db.clone();
+        let cache = self.cache.clone();
+        let prefix = prefix.to_owned();
+        future_to_promise(async move {
+            let entries = load_entries(&db, &prefix).await?;
+            cache.borrow_mut().extend(entries);
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    fn reload(&self) -> Promise {
+        let db = self.db.clone();
+        let keys = self.keys.clone();
+        let cache = self.cache.clone();
+        future_to_promise(async move {
+            *keys.borrow_mut() = load_keys(&db).await?;
+            cache.borrow_mut().clear();
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+}
+
+/// Undoes a write in memory, when its transaction is aborted, e.g. because the quota is exceeded.
+fn on_abort(transaction: &IdbTransaction, undo: impl FnOnce() + 'static) {
+    let committed = transaction_future(transaction);
+    spawn_local(async move {
+        if committed.await.is_err() {
+            undo();
+        }
+    });
+}
+
+fn object_store(db: &IdbDatabase, mode: IdbTransactionMode) -> StorageResult<IdbObjectStore> {
+    db.transaction_with_str_and_mode(STORE_NAME, mode)
+        .and_then(|transaction| transaction.object_store(STORE_NAME))
+        .map_err(|_| StorageError::Unavailable)
+}
+
+/// Creates the object store when the database is opened for the first time.
+fn upgrade_closure(open_request: &IdbOpenDbRequest) -> Closure<dyn FnMut(JsValue)> {
+    let open_request = open_request.clone();
+    Closure::once(move |_: JsValue| {
+        let db: IdbDatabase = open_request
+            .result()
+            .expect("Upgrade without a database")
+            .unchecked_into();
+        if !db.object_store_names().contains(STORE_NAME) {
+            db.create_object_store(STORE_NAME)
+                .expect("Could not create object store");
+        }
+    })
+}
+
+async fn load_keys(db: &IdbDatabase) -> StorageResult<BTreeSet<String>> {
+    let store = object_store(db, IdbTransactionMode::Readonly)?;
+    let request = store
+        .get_all_keys()
+        .map_err(|_| StorageError::Unavailable)?;
+    let keys: Array = request_future(&request)
+        .await
+        .map_err(|_| StorageError::Unavailable)?
+        .unchecked_into();
+    keys.iter()
+        .map(|key| key.as_string().ok_or(StorageError::Corrupt("<key>".into())))
+        .collect()
+}
+
+async fn load_entries(db: &IdbDatabase, prefix: &str) -> StorageResult<Vec<(String, Vec<u8>)>> {
+    let store = object_store(db, IdbTransactionMode::Readonly)?;
+    // `\u{ffff}` sorts after every character that appears in a path.
+    let range = IdbKeyRange::bound(
+        &JsValue::from_str(prefix),
+        &JsValue::from_str(&format!("{}\u{ffff}", prefix)),
+    )
+    .map_err(|_| StorageError::Unavailable)?;
+    let keys_request = store
+        .get_all_keys_with_key(&range)
+        .map_err(|_| StorageError::Unavailable)?;
+    let values_request = store
+        .get_all_with_key(&range)
+        .map_err(|_| StorageError::Unavailable)?;
+    let keys: Array = request_future(&keys_request)
+        .await
+        .map_err(|_| StorageError::Unavailable)?
+        .unchecked_into();
+    let values: Array = request_future(&values_request)
+        .await
+        .map_err(|_| StorageError::Unavailable)?
+        .unchecked_into();
+    keys.iter()
+        .zip(values.iter())
+        .map(|(key, value)| {
+            let key = key
+                .as_string()
+                .ok_or(StorageError::Corrupt("<key>".into()))?;
+            let value: Uint8Array = value
+                .dyn_into()
+                .map_err(|_| StorageError::Corrupt(key.clone()))?;
+            Ok((key, value.to_vec()))
+        })
+        .collect()
+}
+
+/// Waits until an `IDBTransaction` is committed, fails if it is aborted.
+fn transaction_future(transaction: &IdbTransaction) -> JsFuture {
+    let promise = Promise::new(&mut |resolve, reject| {
+        // Only one of the events fires, so the handler runs once and is freed afterwards.
+        let on_done = Closure::once_into_js(move |event: Event| {
+            if event.type_() == "complete" {
+                let _ = resolve.call0(&JsValue::NULL);
+            } else {
+                let _ = reject.call1(&JsValue::NULL, &event);
+            }
+        });
+        transaction.set_oncomplete(Some(on_done.unchecked_ref()));
+        transaction.set_onabort(Some(on_done.unchecked_ref()));
+    });
+    JsFuture::from(promise)
+}
+
+/// Waits for an `IDBRequest` to finish and returns its result.
+pub(crate) fn request_future(request: &IdbRequest) -> JsFuture {
+    let promise = Promise::new(&mut |resolve, reject| {
+        let done_request = request.clone();
+        let on_done = Closure::once_into_js(move |event: Event| {
+            if event.type_() == "success" {
+                let result = done_request.result().unwrap_or(JsValue::UNDEFINED);
+                let _ = resolve.call1(&JsValue::NULL, &result);
+            } else {
+                let _ = reject.call1(&JsValue::NULL, &event);
+            }
+        });
+        request.set_onsuccess(Some(on_done.unchecked_ref()));
+        request.set_onerror(Some(on_done.unchecked_ref()));
+    });
+    JsFuture::from(promise)
+}
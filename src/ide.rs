@@ -0,0 +1,255 @@
+use js_sys::{Array, Object};
+use typst::doc::{Frame, Position};
+use typst::geom::{Abs, Point};
+use typst::ide::{Completion, CompletionKind, Jump, Tooltip};
+use typst::syntax::Source;
+use wasm_bindgen::prelude::*;
+
+use crate::diagnostic::set;
+use crate::file::project_file_id;
+use crate::{file_error, SystemWorld};
+
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug)]
+pub struct WasmCompletion {
+    /// One of `syntax`, `function`, `parameter`, `constant` and `symbol`.
+    pub kind: String,
+    pub label: String,
+    /// The snippet to insert, `${...}` marks placeholders. Falls back to the label.
+    pub apply: Option<String>,
+    pub detail: Option<String>,
+}
+
+impl From<Completion> for WasmCompletion {
+    fn from(value: Completion) -> Self {
+        let kind = match value.kind {
+            CompletionKind::Syntax => "syntax",
+            CompletionKind::Func => "function",
+            CompletionKind::Param => "parameter",
+            CompletionKind::Constant => "constant",
+            CompletionKind::Symbol(_) => "symbol",
+        };
+        Self {
+            kind: kind.to_owned(),
+            label: value.label.to_string(),
+            apply: value.apply.map(|apply| apply.to_string()),
+            detail: value.detail.map(|detail| detail.to_string()),
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub struct WasmCompletions {
+    /// The byte offset from which on the completions replace the text.
+    pub from: usize,
+    pub from_utf16: usize,
+    #[wasm_bindgen(skip)]
+    pub items: Vec<WasmCompletion>,
+}
+
+#[wasm_bindgen]
+impl WasmCompletions {
+    #[wasm_bindgen(getter)]
+    pub fn items(&self) -> Array {
+        self.items.iter().cloned().map(JsValue::from).collect()
+    }
+}
+
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug)]
+pub struct WasmTooltip {
+    /// Either `text` or `code`, code should be highlighted as Typst code.
+    pub kind: String,
+    pub text: String,
+}
+
+impl From<Tooltip> for WasmTooltip {
+    fn from(value: Tooltip) -> Self {
+        let (kind, text) = match value {
+            Tooltip::Text(text) => ("text", text),
+            Tooltip::Code(code) => ("code", code),
+        };
+        Self {
+            kind: kind.to_owned(),
+            text: text.to_string(),
+        }
+    }
+}
+
+/// A point on a page of the compiled document.
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug)]
+pub struct WasmPosition {
+    /// The page number, starting at one.
+    pub page: usize,
+    /// The coordinates in points, starting at the top left corner of the page.
+    pub x: f64,
+    pub y: f64,
+}
+
+impl From<Position> for WasmPosition {
+    fn from(value: Position) -> Self {
+        Self {
+            page: value.page.get(),
+            x: value.point.x.to_pt(),
+            y: value.point.y.to_pt(),
+        }
+    }
+}
+
+impl WasmPosition {
+    /// A plain object with the same properties, which can be sent with `postMessage`.
+    pub fn to_object(&self) -> Object {
+        let object = Object::new();
+        set(&object, "page", &JsValue::from(self.page as f64));
+        set(&object, "x", &JsValue::from(self.x));
+        set(&object, "y", &JsValue::from(self.y));
+        object
+    }
+}
+
+/// The destination of a click in the preview.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug)]
+pub struct WasmJump {
+    /// One of `source`, `url` and `position`, only the matching fields are set.
+    pub kind: String,
+    /// The rooted path of the source file.
+    pub path: Option<String>,
+    /// The package of the source file as `@namespace/name:version`.
+    pub package: Option<String>,
+    /// The byte offset in the source file.
+    pub offset: Option<usize>,
+    pub offset_utf16: Option<usize>,
+    pub url: Option<String>,
+    pub position: Option<WasmPosition>,
+}
+
+impl WasmJump {
+    fn new(kind: &str) -> Self {
+        Self {
+            kind: kind.to_owned(),
+            path: None,
+            package: None,
+            offset: None,
+            offset_utf16: None,
+            url: None,
+            position: None,
+        }
+    }
+}
+
+impl WasmJump {
+    /// A plain object with the same properties, which can be sent with `postMessage`.
+    pub fn to_object(&self) -> Object {
+        let object = Object::new();
+        set(&object, "kind", &JsValue::from_str(&self.kind));
+        set(&object, "path", &self.path.clone().into());
+        set(&object, "package", &self.package.clone().into());
+        set(
+            &object,
+            "offset",
+            &self.offset.map(|offset| offset as f64).into(),
+        );
+        set(
+            &object,
+            "offset_utf16",
+            &self.offset_utf16.map(|offset| offset as f64).into(),
+        );
+        set(&object, "url", &self.url.clone().into());
+        let position = self.position.map(|position| position.to_object());
+        set(&object, "position", &position.into());
+        object
+    }
+}
+
+impl SystemWorld {
+    /// The pages of the last compiled document, empty if nothing was compiled yet.
+    fn frames(&self) -> &[Frame] {
+        self.document
+            .as_ref()
+            .map_or(&[], |document| &document.pages[..])
+    }
+
+    /// The project source at `path`, if `cursor` is a valid byte offset in it.
+    fn source_at(&self, path: &str, cursor: usize) -> Result<Source, JsValue> {
+        let source = self.vfs.source(project_file_id(path)).map_err(file_error)?;
+        if cursor > source.len_bytes() || !source.text().is_char_boundary(cursor) {
+            return Err(JsValue::from_str(&format!("invalid cursor {}", cursor)));
+        }
+        Ok(source)
+    }
+}
+
+#[wasm_bindgen]
+impl SystemWorld {
+    /// Completions at the byte offset `cursor` of a project file.
+    ///
+    /// `explicit` should be set, if the user requested the completions, e.g. with Ctrl+Space.
+    /// Package imports are completed with the packages, which were installed at the last
+    /// compilation.
+    pub fn autocomplete(
+        &self,
+        path: &str,
+        cursor: usize,
+        explicit: bool,
+    ) -> Result<Option<WasmCompletions>, JsValue> {
+        let source = self.source_at(path, cursor)?;
+        let completions = typst::ide::autocomplete(self, self.frames(), &source, cursor, explicit);
+        Ok(completions.map(|(from, items)| WasmCompletions {
+            from,
+            from_utf16: source.byte_to_utf16(from).unwrap_or(from),
+            items: items.into_iter().map(WasmCompletion::from).collect(),
+        }))
+    }
+
+    /// The tooltip for the byte offset `cursor` of a project file.
+    ///
+    /// Values are evaluated in the context of the last compiled document.
+    pub fn tooltip(&self, path: &str, cursor: usize) -> Result<Option<WasmTooltip>, JsValue> {
+        let source = self.source_at(path, cursor)?;
+        let tooltip = typst::ide::tooltip(self, self.frames(), &source, cursor);
+        Ok(tooltip.map(WasmTooltip::from))
+    }
+
+    /// Where a click at `x` and `y` (in points) on `page` (starting at one) of the last compiled
+    /// document leads to.
+    pub fn jump_from_click(&self, page: usize, x: f64, y: f64) -> Option<WasmJump> {
+        let frames = self.frames();
+        let frame = frames.get(page.checked_sub(1)?)?;
+        let click = Point::new(Abs::pt(x), Abs::pt(y));
+        let jump = typst::ide::jump_from_click(self, frames, frame, click)?;
+        Some(match jump {
+            Jump::Source(id, offset) => {
+                let source = self.vfs.source(id).ok()?;
+                WasmJump {
+                    path: Some(id.vpath().as_rooted_path().to_string_lossy().into_owned()),
+                    package: id.package().map(|spec| spec.to_string()),
+                    offset: Some(offset),
+                    offset_utf16: source.byte_to_utf16(offset),
+                    ..WasmJump::new("source")
+                }
+            }
+            Jump::Url(url) => WasmJump {
+                url: Some(url.to_string()),
+                ..WasmJump::new("url")
+            },
+            Jump::Position(position) => WasmJump {
+                position: Some(position.into()),
+                ..WasmJump::new("position")
+            },
+        })
+    }
+
+    /// The position in the last compiled document, which was produced by the byte offset `cursor`
+    /// of a project file.
+    pub fn jump_from_cursor(
+        &self,
+        path: &str,
+        cursor: usize,
+    ) -> Result<Option<WasmPosition>, JsValue> {
+        let source = self.source_at(path, cursor)?;
+        let position = typst::ide::jump_from_cursor(self.frames(), &source, cursor);
+        Ok(position.map(WasmPosition::from))
+    }
+}
@@ -0,0 +1,93 @@
+use comemo::Track;
+use js_sys::Array;
+use typst::eval::{eval_string, EvalMode, Scope};
+use typst::model::{Content, Element, LocatableSelector, Selector, StyleChain};
+use typst::syntax::Span;
+use typst::World;
+use typst_library::meta::HeadingElem;
+use wasm_bindgen::prelude::*;
+
+use crate::diagnostic::{self, WasmSpan};
+use crate::ide::WasmPosition;
+use crate::SystemWorld;
+
+/// A heading of the compiled document.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug)]
+pub struct WasmHeading {
+    /// The nesting level, starting at one.
+    pub level: usize,
+    /// The body of the heading as plain text.
+    pub text: String,
+    /// Where the heading was placed in the document.
+    pub position: Option<WasmPosition>,
+    /// Where the heading is defined in the sources.
+    pub span: Option<WasmSpan>,
+}
+
+#[wasm_bindgen]
+impl SystemWorld {
+    /// The headings of the last compiled document in document order.
+    pub fn outline(&self) -> Array {
+        let Some(document) = &self.document else {
+            return Array::new();
+        };
+        let introspector = &document.introspector;
+        introspector
+            .query(&Selector::Elem(HeadingElem::func(), None))
+            .iter()
+            .filter_map(|elem| {
+                let heading = elem.to::<HeadingElem>()?;
+                Some(WasmHeading {
+                    level: heading.level(StyleChain::default()).get(),
+                    text: heading.body().plain_text().to_string(),
+                    position: elem
+                        .location()
+                        .map(|location| introspector.position(location).into()),
+                    span: WasmSpan::resolve(self, elem.span()),
+                })
+            })
+            .map(JsValue::from)
+            .collect()
+    }
+
+    /// Queries the last compiled document like `typst query` and returns the result as JSON.
+    ///
+    /// `selector` is typst code, e.g. `heading`, `<label>` or `figure.where(kind: table)`. If
+    /// `field` is given, only that field of each element is returned.
+    pub fn query(&self, selector: &str, field: Option<String>) -> Result<String, JsValue> {
+        let document = self
+            .document
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("there is no compiled document yet"))?;
+        let world: &dyn World = self;
+        let selector = eval_string(
+            world.track(),
+            selector,
+            Span::detached(),
+            EvalMode::Code,
+            Scope::default(),
+        )
+        .map_err(|errors| diagnostic::to_js_array(diagnostic::resolve(self, errors.iter())))?
+        .cast::<LocatableSelector>()
+        .map_err(|error| JsValue::from_str(&error))?;
+
+        let elements: Vec<Content> = document
+            .introspector
+            .query(&selector.0)
+            .into_iter()
+            .map(|elem| elem.into_inner())
+            .collect();
+        let json = match field {
+            Some(field) => {
+                let values: Vec<_> = elements
+                    .iter()
+                    .filter_map(|elem| elem.field(&field))
+                    .collect();
+                serde_json::to_string(&values)
+            }
+            None => serde_json::to_string(&elements),
+        };
+        json.map_err(|error| JsValue::from_str(&error.to_string()))
+    }
+}
@@ -1,76 +1,61 @@
+use web_sys::Storage;
 
-
-
-use web_sys::{window, Storage};
-
-pub fn local_storage() -> Storage {
-    window()
-        .expect("Could not access window")
-        .local_storage()
-        .expect("Could not access local storage")
-        .expect("Could not find a local storage")
-}
+use crate::storage::{local_storage, FileStorage, StorageError, StorageResult};
 
 pub struct LFS {
     storage: Storage,
 }
 
 impl LFS {
-    pub fn new() -> Self {
-        Self {
-            storage: local_storage(),
-        }
+    /// Opens the local storage, returns `None` if there is none, e.g. inside a Web Worker.
+    pub fn new() -> Option<Self> {
+        Some(Self {
+            storage: local_storage()?,
+        })
     }
+}
 
-    pub fn exists(&self, key: &str) -> bool {
-        self.storage
-            .get(key)
-            .expect("Could not use local storage")
-            .is_some()
+impl FileStorage for LFS {
+    fn exists(&self, key: &str) -> bool {
+        self.get(key).is_some()
     }
 
-    pub fn get(&self, key: &str) -> Option<String> {
-        self.storage.get(key).expect("Could not use local storage")
+    fn get(&self, key: &str) -> Option<String> {
+        self.storage.get(key).ok().flatten()
     }
 
-    pub fn get_bytes(&self, key: &str) -> Option<Vec<u8>> {
-        self.get(key)
-            .map(|text| hex::decode(text).expect("The file did not contain binary data"))
+    fn get_bytes(&self, key: &str) -> Option<Vec<u8>> {
+        self.get(key).and_then(|text| hex::decode(text).ok())
     }
 
-    pub fn set(&self, key: &str, value: &str) {
-        self.storage
-            .set(key, value)
-            .expect("Could not use local storage");
+    fn set(&self, key: &str, value: &str) -> StorageResult<()> {
+        // Browsers throw a `QuotaExceededError` if the origin quota is used up.
+        self.storage.set(key, value).map_err(|_| StorageError::Full)
     }
 
-    pub fn set_bytes(&self, key: &str, value: &[u8]) {
-        self.set(key, &hex::encode(value));
+    fn set_bytes(&self, key: &str, value: &[u8]) -> StorageResult<()> {
+        self.set(key, &hex::encode(value))
     }
 
-    pub fn delete(&self, key: &str) -> bool {
+    fn delete(&self, key: &str) -> bool {
         if !self.exists(key) {
             return false;
         }
-        self.storage
-            .delete(key)
-            .expect("Could not use local storage");
-        true
+        self.storage.delete(key).is_ok()
+    }
+
+    /// Keys and the hex encoded values are stored as UTF-16, with two bytes per character.
+    fn stored_size(&self, key: &str) -> usize {
+        self.get(key).map_or(0, |value| {
+            (key.encode_utf16().count() + value.encode_utf16().count()) * 2
+        })
     }
 
-    pub fn list(&self) -> Vec<String> {
-        let count: u32 = self
-            .storage
-            .length()
-            .expect("Could not count items in local storage");
+    fn list(&self) -> Vec<String> {
+        let count: u32 = self.storage.length().unwrap_or(0);
         (0..count)
             .into_iter()
-            .map(|index| {
-                self.storage
-                    .key(index)
-                    .expect("Could not read key from local storage")
-                    .unwrap()
-            })
+            .filter_map(|index| self.storage.key(index).ok().flatten())
             .collect()
     }
 }
@@ -0,0 +1,89 @@
+//! Integrity hashes of installed packages and the lock file of a project.
+//!
+//! Every installed package records the SHA-256 of its archive and of its extracted files in its
+//! directory marker `packages/{namespace}/{name}/{version}/.`, they are checked again whenever the
+//! package is loaded. The lock file pins the hashes of the packages, which a project uses.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The project file, which lists the packages of the project together with their hashes.
+pub const LOCK_FILE_NAME: &str = "/typst.lock";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageHashes {
+    /// The SHA-256 of the downloaded or uploaded archive, if the package was installed from one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
+    /// The SHA-256 of the extracted files, see [`files_hash`].
+    pub files: String,
+}
+
+impl PackageHashes {
+    /// Whether the hashes describe the same package, archives are only compared if both are known.
+    pub fn matches(&self, other: &Self) -> bool {
+        let archives_match = match (&self.archive, &other.archive) {
+            (Some(archive), Some(other)) => archive == other,
+            _ => true,
+        };
+        archives_match && self.files == other.files
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LockFile {
+    /// The hashes by package spec, e.g. `@preview/cetz:0.1.2`.
+    pub packages: BTreeMap<String, PackageHashes>,
+}
+
+impl LockFile {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        serde_json::from_str(text).map_err(|error| format!("invalid {}: {}", LOCK_FILE_NAME, error))
+    }
+
+    pub fn to_text(&self) -> String {
+        serde_json::to_string_pretty(self).expect("The lock file is always serializable") + "\n"
+    }
+}
+
+pub fn sha256(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Hashes the relative paths and contents of the files of a package, independent of their order.
+pub fn files_hash<'a>(files: impl IntoIterator<Item = (&'a str, &'a [u8])>) -> String {
+    let mut files: Vec<_> = files.into_iter().collect();
+    files.sort();
+    let mut hasher = Sha256::new();
+    for (path, content) in files {
+        hasher.update((path.len() as u64).to_le_bytes());
+        hasher.update(path.as_bytes());
+        hasher.update((content.len() as u64).to_le_bytes());
+        hasher.update(content);
+    }
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_hash_ignores_file_order() {
+        let files = [
+            ("lib.typ", &b"#let x = 1"[..]),
+            ("typst.toml", &b"[package]"[..]),
+        ];
+        let reversed = [files[1], files[0]];
+        assert_eq!(files_hash(files), files_hash(reversed));
+    }
+
+    #[test]
+    fn files_hash_separates_paths_and_contents() {
+        let joined = files_hash([("ab", &b"c"[..])]);
+        assert_ne!(joined, files_hash([("a", &b"bc"[..])]));
+        assert_ne!(joined, files_hash([("ab", &b"d"[..])]));
+    }
+}
@@ -1,23 +1,25 @@
 use comemo::Prehashed;
 use flate2::Compression;
 use once_cell::unsync::OnceCell;
+use typst::doc::Document;
 use typst::eval::Tracer;
 
 use std::io::Write;
 use std::mem;
+use std::rc::Rc;
 
 use std::io::Read;
 use time::{Date, Month};
-use typst::geom::Color;
 
-use js_sys::{Array, ArrayBuffer};
+use js_sys::{Array, ArrayBuffer, Promise};
 use wasm_bindgen::prelude::*;
-use web_sys::Blob;
+use wasm_bindgen_futures::{future_to_promise, spawn_local};
+use web_sys::{console, Blob};
 
 use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use typst::{
-    diag::FileResult,
+    diag::{FileError, FileResult},
     eval::{Bytes, Library},
     font::{Font, FontBook},
     syntax::{FileId, PackageSpec, Source},
@@ -27,15 +29,31 @@ extern crate console_error_panic_hook;
 use std::panic;
 
 pub mod compat;
+pub mod diagnostic;
 mod file;
+pub mod highlight;
+mod idb;
+pub mod ide;
+pub mod introspect;
 pub mod lfs;
+pub mod lock;
+pub mod manifest;
 pub mod package;
+pub mod render;
+pub mod storage;
+pub mod worker;
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine as _;
+use compat::WasmPackageSpec;
+use diagnostic::WasmDiagnostic;
 use file::VFS;
+use render::{PageCache, RenderedPages};
+use storage::{default_storage, FileStorage, StorageBackend};
 
 pub static MAIN_SOURCE_NAME: &'static str = "/main.typ";
 
+pub type Diagnostics = Vec<WasmDiagnostic>;
+
 fn main() {
     panic::set_hook(Box::new(console_error_panic_hook::hook));
 }
@@ -64,49 +82,88 @@ pub struct SystemWorld {
     book: Prehashed<FontBook>,
     fonts: Vec<FontSlot>,
     vfs: VFS,
+    page_cache: PageCache,
+    /// The document of the last successful compilation.
+    document: Option<Document>,
+    /// The installed packages with their descriptions, as of the last compilation.
+    packages: Vec<(PackageSpec, Option<typst::diag::EcoString>)>,
 }
 
 impl SystemWorld {
-    pub fn compile_to_pdf_bytes(&mut self, source: String) -> Result<Vec<u8>, JsValue> {
-        self.vfs.set_main(source);
+    pub fn from_storage(storage: Rc<dyn FileStorage>) -> Self {
+        Self {
+            library: Prehashed::new(typst_library::build()),
+            book: Prehashed::new(FontBook::new()),
+            fonts: vec![],
+            vfs: VFS::new(storage),
+            page_cache: PageCache::default(),
+            document: None,
+            packages: vec![],
+        }
+    }
+
+    /// Compiles the main source, the errors and warnings are returned as `WasmDiagnostic`s.
+    ///
+    /// If `source` is given, it replaces the main source first.
+    fn compile(&mut self, source: Option<String>) -> Result<Compiled<Document>, Diagnostics> {
+        if let Some(source) = source {
+            self.vfs.set_main(source);
+        }
+        self.vfs.reset_packages();
+        self.packages = self.vfs.installed_packages();
         let mut tracer = Tracer::default();
-        match typst::compile(self, &mut tracer) {
+        let result = typst::compile(self, &mut tracer);
+        let warnings = tracer.warnings();
+        if result.is_ok() {
+            if let Err(error) = self.vfs.update_lock_file() {
+                console::warn_1(&format!("Could not update the lock file: {}", error).into());
+            }
+        }
+        let saved = self.vfs.save();
+        spawn_local(async move {
+            if let Err(error) = saved.await {
+                console::warn_1(&format!("Could not save the project files: {}", error).into());
+            }
+        });
+        match result {
             Ok(document) => {
-                let render = typst::export::pdf(&document, None, None);
-                Ok(render)
+                self.document = Some(document.clone());
+                Ok(Compiled {
+                    output: document,
+                    warnings: diagnostic::resolve(self, warnings.iter()),
+                })
             }
-            Err(errors) => Err(errors
-                .into_iter()
-                .map(|error| JsValue::from_str(&error.message))
-                .collect::<Array>()
-                .into()),
+            Err(errors) => Err(diagnostic::resolve(
+                self,
+                errors.iter().chain(warnings.iter()),
+            )),
         }
     }
 
+    pub fn compile_to_pdf_bytes(
+        &mut self,
+        source: Option<String>,
+    ) -> Result<Compiled<Vec<u8>>, Diagnostics> {
+        let compiled = self.compile(source)?;
+        Ok(compiled.map(|document| typst::export::pdf(&document, None, None)))
+    }
+
+    /// Renders the pages to PNG, pages which did not change since the last call are reused.
     pub fn compile_to_images_bytes(
         &mut self,
-        source: String,
+        source: Option<String>,
         pixel_per_pt: f32,
-    ) -> Result<Vec<Vec<u8>>, JsValue> {
-        self.vfs.set_main(source);
-        let mut tracer = Tracer::default();
-        match typst::compile(self, &mut tracer) {
-            Ok(document) => {
-                let fill = Color::WHITE;
-                let images = document
-                    .pages
-                    .into_iter()
-                    .map(|page| typst::export::render(&page, pixel_per_pt, fill))
-                    .map(|image| image.encode_png().expect("Could not encode as PNG"))
-                    .collect();
-                Ok(images)
-            }
-            Err(errors) => Err(errors
-                .into_iter()
-                .map(|error| JsValue::from_str(&error.message))
-                .collect::<Array>()
-                .into()),
-        }
+    ) -> Result<Compiled<RenderedPages>, Diagnostics> {
+        let compiled = self.compile(source)?;
+        Ok(compiled.map(|document| self.page_cache.render(&document, pixel_per_pt)))
+    }
+
+    pub fn compile_to_svg_strings(
+        &mut self,
+        source: Option<String>,
+    ) -> Result<Compiled<Vec<String>>, Diagnostics> {
+        let compiled = self.compile(source)?;
+        Ok(compiled.map(|document| document.pages.iter().map(typst::export::svg).collect()))
     }
 }
 
@@ -114,50 +171,188 @@ impl SystemWorld {
 impl SystemWorld {
     #[wasm_bindgen(constructor)]
     pub fn new() -> SystemWorld {
-        Self {
-            library: Prehashed::new(typst_library::build()),
-            book: Prehashed::new(FontBook::new()),
-            fonts: vec![],
-            vfs: VFS::new(),
-        }
+        Self::from_storage(default_storage())
     }
 
-    pub fn compile_to_pdf(&mut self, source: String) -> Result<String, JsValue> {
-        let bytes = self.compile_to_pdf_bytes(source)?;
-        let uint8arr = js_sys::Uint8Array::new(&unsafe { js_sys::Uint8Array::view(&bytes) }.into());
-        let array = js_sys::Array::new();
-        array.push(&uint8arr.buffer());
-        let blob = Blob::new_with_u8_array_sequence_and_options(
-            &array,
-            web_sys::BlobPropertyBag::new().type_("application/pdf"),
-        )?;
-        web_sys::Url::create_object_url_with_blob(&blob)
+    pub fn with_storage(storage: &StorageBackend) -> SystemWorld {
+        Self::from_storage(storage.storage())
     }
 
+    pub fn compile_to_pdf(&mut self, source: Option<String>) -> Result<CompileResult, JsValue> {
+        let compiled = self
+            .compile_to_pdf_bytes(source)
+            .map_err(diagnostic::to_js_array)?;
+        let url = blob_url(&compiled.output, "application/pdf")?;
+        Ok(CompileResult {
+            output: JsValue::from_str(&url),
+            warnings: compiled.warnings,
+        })
+    }
+
+    /// Returns `RenderedPages`, which contain a PNG blob URL per page and the changed page indices.
     pub fn compile_to_images(
         &mut self,
-        source: String,
+        source: Option<String>,
         pixel_per_pt: f32,
-    ) -> Result<js_sys::Array, JsValue> {
-        let bytes = self.compile_to_images_bytes(source, pixel_per_pt)?;
-        let urls = bytes
-            .into_iter()
-            .map(|bytes| {
-                let uint8arr =
-                    js_sys::Uint8Array::new(&unsafe { js_sys::Uint8Array::view(&bytes) }.into());
-                let array = js_sys::Array::new();
-                array.push(&uint8arr.buffer());
-                let blob = Blob::new_with_u8_array_sequence_and_options(
-                    &array,
-                    web_sys::BlobPropertyBag::new().type_("image/png"),
-                )
-                .expect("Coult not create image BLOB's");
-                let url = web_sys::Url::create_object_url_with_blob(&blob)
-                    .expect("Could not create URL for BLOB");
-                JsValue::from_str(&url)
-            })
+    ) -> Result<CompileResult, JsValue> {
+        let compiled = self
+            .compile_to_images_bytes(source, pixel_per_pt)
+            .map_err(diagnostic::to_js_array)?;
+        Ok(CompileResult {
+            output: compiled.output.into(),
+            warnings: compiled.warnings,
+        })
+    }
+
+    /// Compiles to one SVG string per page.
+    pub fn compile_to_svg(&mut self, source: Option<String>) -> Result<CompileResult, JsValue> {
+        let compiled = self
+            .compile_to_svg_strings(source)
+            .map_err(diagnostic::to_js_array)?;
+        let svgs: Array = compiled
+            .output
+            .iter()
+            .map(|svg| JsValue::from_str(svg))
             .collect();
-        Ok(urls)
+        Ok(CompileResult {
+            output: svgs.into(),
+            warnings: compiled.warnings,
+        })
+    }
+
+    /// Compiles to one SVG blob URL per page.
+    pub fn compile_to_svg_urls(
+        &mut self,
+        source: Option<String>,
+    ) -> Result<CompileResult, JsValue> {
+        let compiled = self
+            .compile_to_svg_strings(source)
+            .map_err(diagnostic::to_js_array)?;
+        let urls = compiled
+            .output
+            .iter()
+            .map(|svg| {
+                let url = blob_url(svg.as_bytes(), "image/svg+xml")?;
+                Ok(JsValue::from_str(&url))
+            })
+            .collect::<Result<Array, JsValue>>()?;
+        Ok(CompileResult {
+            output: urls.into(),
+            warnings: compiled.warnings,
+        })
+    }
+
+    /// Lists the paths of all files in the project, including the main file.
+    pub fn list_files(&self) -> Array {
+        self.vfs
+            .list_files()
+            .into_iter()
+            .map(|path| JsValue::from_str(&path))
+            .collect()
+    }
+
+    pub fn create_file(&mut self, path: &str, text: String) -> Result<(), JsValue> {
+        self.vfs.create_file(path, text).map_err(file_error)
+    }
+
+    /// Creates or replaces a source file.
+    pub fn set_source(&mut self, path: &str, text: String) {
+        self.vfs.set_source(path, text);
+    }
+
+    pub fn update_file(&mut self, path: &str, text: &str) -> Result<(), JsValue> {
+        self.vfs.update_file(path, text).map_err(file_error)
+    }
+
+    /// Adds a binary file such as an image, a data file or a bibliography to the project.
+    ///
+    /// The returned promise is rejected, if the file could not be stored, e.g. because the
+    /// storage is full. The file is still part of the project until the page is reloaded.
+    pub fn upload_file(&mut self, path: &str, content: ArrayBuffer) -> Result<Promise, JsValue> {
+        let bytes = js_sys::Uint8Array::new(&content).to_vec();
+        self.vfs
+            .upload_file(path, Bytes::from(bytes))
+            .map_err(file_error)?;
+        let saved = self.vfs.save();
+        Ok(future_to_promise(async move {
+            saved.await?;
+            Ok(JsValue::UNDEFINED)
+        }))
+    }
+
+    /// Replaces the byte range `start..end` of a project file with `text`.
+    ///
+    /// Only the edited part of the syntax tree is reparsed, pass `undefined` as the source to the
+    /// compile functions afterwards to compile the edited sources. Returns the reparsed byte range
+    /// as `[start, end]`, which can be passed to `highlight_range`.
+    pub fn edit(
+        &mut self,
+        path: &str,
+        start: usize,
+        end: usize,
+        text: &str,
+    ) -> Result<Vec<u32>, JsValue> {
+        self.vfs
+            .edit_file(path, start..end, text)
+            .map(|range| vec![range.start as u32, range.end as u32])
+            .map_err(file_error)
+    }
+
+    /// Like `edit`, but `start` and `end` are UTF-16 offsets as used by JavaScript strings.
+    ///
+    /// The returned range is still given in bytes.
+    pub fn edit_utf16(
+        &mut self,
+        path: &str,
+        start: usize,
+        end: usize,
+        text: &str,
+    ) -> Result<Vec<u32>, JsValue> {
+        self.vfs
+            .edit_file_utf16(path, start..end, text)
+            .map(|range| vec![range.start as u32, range.end as u32])
+            .map_err(file_error)
+    }
+
+    pub fn rename_file(&mut self, from: &str, to: &str) -> Result<(), JsValue> {
+        self.vfs.rename_file(from, to).map_err(file_error)
+    }
+
+    pub fn delete_file(&mut self, path: &str) -> Result<(), JsValue> {
+        self.vfs.delete_file(path).map_err(file_error)
+    }
+
+    /// Has to be awaited before compiling, if the storage reads asynchronously.
+    pub fn prefetch(&self) -> js_sys::Promise {
+        self.vfs.prefetch()
+    }
+
+    /// The packages, which the last compilation imported, but which are not installed.
+    ///
+    /// They can be installed with the `PackageManager` before compiling again.
+    pub fn missing_packages(&self) -> Array {
+        self.vfs
+            .missing_packages()
+            .into_iter()
+            .map(|spec| JsValue::from(WasmPackageSpec::from(spec)))
+            .collect()
+    }
+
+    /// The packages, which the project files import or include directly, as
+    /// `@namespace/name:version`.
+    pub fn imported_packages(&self) -> Array {
+        self.vfs
+            .imported_packages()
+            .iter()
+            .map(|spec| JsValue::from_str(&spec.to_string()))
+            .collect()
+    }
+
+    /// The text of `/typst.lock`, which pins the hashes of the used packages, if there is one.
+    ///
+    /// It has to be shared together with the main source, restore it with `set_source`.
+    pub fn lock_file(&self) -> Option<String> {
+        self.vfs.lock_file_text()
     }
 
     pub fn add_fonts(&mut self, fonts: Array) {
@@ -180,9 +375,43 @@ impl SystemWorld {
     }
 }
 
+/// The output of a successful compilation together with its warnings.
+pub struct Compiled<T> {
+    pub output: T,
+    pub warnings: Diagnostics,
+}
+
+impl<T> Compiled<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Compiled<U> {
+        Compiled {
+            output: f(self.output),
+            warnings: self.warnings,
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub struct CompileResult {
+    output: JsValue,
+    warnings: Diagnostics,
+}
+
+#[wasm_bindgen]
+impl CompileResult {
+    /// The compiled document, its type depends on the compile function.
+    #[wasm_bindgen(getter)]
+    pub fn output(&self) -> JsValue {
+        self.output.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn warnings(&self) -> Array {
+        diagnostic::to_js_array(self.warnings.clone())
+    }
+}
+
 impl World for SystemWorld {
     fn packages(&self) -> &[(PackageSpec, Option<typst::diag::EcoString>)] {
-        &[]
+        &self.packages
     }
 
     fn today(&self, _offset: Option<i64>) -> Option<typst::eval::Datetime> {
@@ -222,6 +451,22 @@ impl World for SystemWorld {
     }
 }
 
+/// Creates an object URL for `bytes`, which has to be revoked by the caller.
+fn blob_url(bytes: &[u8], mime_type: &str) -> Result<String, JsValue> {
+    let uint8arr = js_sys::Uint8Array::new(&unsafe { js_sys::Uint8Array::view(bytes) }.into());
+    let array = js_sys::Array::new();
+    array.push(&uint8arr.buffer());
+    let blob = Blob::new_with_u8_array_sequence_and_options(
+        &array,
+        web_sys::BlobPropertyBag::new().type_(mime_type),
+    )?;
+    web_sys::Url::create_object_url_with_blob(&blob)
+}
+
+fn file_error(error: FileError) -> JsValue {
+    JsValue::from_str(&error.to_string())
+}
+
 /// Holds details about the location of a font and lazily the font itself.
 struct FontSlot {
     buffer: Bytes,
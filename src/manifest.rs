@@ -0,0 +1,145 @@
+//! The `typst.toml` manifest, which every package contains at its root.
+
+use js_sys::Array;
+use serde::Deserialize;
+use typst::diag::EcoString;
+use typst::syntax::{PackageSpec, PackageVersion};
+use wasm_bindgen::prelude::*;
+
+/// The version of the embedded compiler.
+///
+/// This has to match the `tag` of the `typst` dependency in `Cargo.toml`.
+const TYPST_VERSION: &str = "0.9.0";
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PackageManifest {
+    pub package: PackageInfo,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PackageInfo {
+    pub name: String,
+    pub version: String,
+    /// The path of the file, which is evaluated when the package is imported.
+    pub entrypoint: String,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    pub license: Option<String>,
+    pub description: Option<String>,
+    /// The minimum compiler version, which the package supports.
+    pub compiler: Option<String>,
+}
+
+impl PackageManifest {
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let text =
+            std::str::from_utf8(bytes).map_err(|_| "typst.toml is not valid UTF-8".to_owned())?;
+        toml::from_str(text).map_err(|error| format!("invalid typst.toml: {}", error.message()))
+    }
+
+    /// Checks that the manifest belongs to `spec` and that the embedded compiler is supported.
+    pub fn validate(&self, spec: &PackageSpec) -> Result<(), String> {
+        if self.package.name != spec.name.as_str() {
+            return Err(format!(
+                "package manifest contains mismatched name `{}`",
+                self.package.name
+            ));
+        }
+        let version = parse_version(&self.package.version)?;
+        if version != spec.version {
+            return Err(format!(
+                "package manifest contains mismatched version {}",
+                version
+            ));
+        }
+        if let Some(compiler) = &self.package.compiler {
+            let required = parse_version(compiler)?;
+            let current = parse_version(TYPST_VERSION)?;
+            if (required.major, required.minor, required.patch)
+                > (current.major, current.minor, current.patch)
+            {
+                return Err(format!(
+                    "package requires typst {} or newer (current version is {})",
+                    required, current
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_version(version: &str) -> Result<PackageVersion, String> {
+    version
+        .parse()
+        .map_err(|error: EcoString| format!("invalid version {}: {}", version, error))
+}
+
+/// The metadata of an installed package, as declared by its manifest.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug)]
+pub struct WasmPackageInfo {
+    pub entrypoint: String,
+    #[wasm_bindgen(skip)]
+    pub authors: Vec<String>,
+    pub license: Option<String>,
+    pub description: Option<String>,
+    pub compiler: Option<String>,
+}
+
+impl From<PackageInfo> for WasmPackageInfo {
+    fn from(value: PackageInfo) -> Self {
+        Self {
+            entrypoint: value.entrypoint,
+            authors: value.authors,
+            license: value.license,
+            description: value.description,
+            compiler: value.compiler,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl WasmPackageInfo {
+    #[wasm_bindgen(getter)]
+    pub fn authors(&self) -> Array {
+        self.authors
+            .iter()
+            .map(|author| JsValue::from_str(author))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(name: &str, version: &str, compiler: Option<&str>) -> PackageManifest {
+        let compiler = compiler.map_or(String::new(), |compiler| {
+            format!("compiler = \"{}\"\n", compiler)
+        });
+        let text = format!(
+            "[package]\nname = \"{}\"\nversion = \"{}\"\nentrypoint = \"lib.typ\"\n{}",
+            name, version, compiler
+        );
+        PackageManifest::parse(text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn validate_checks_name_and_version() {
+        let spec: PackageSpec = "@preview/my-package:0.1.0".parse().unwrap();
+        let valid = |name, version| manifest(name, version, None).validate(&spec).is_ok();
+        assert!(valid("my-package", "0.1.0"));
+        assert!(!valid("other", "0.1.0"));
+        assert!(!valid("my-package", "0.1.1"));
+        assert!(!valid("my-package", "latest"));
+    }
+
+    #[test]
+    fn validate_checks_compiler_version() {
+        let spec: PackageSpec = "@preview/my-package:0.1.0".parse().unwrap();
+        let supported = manifest("my-package", "0.1.0", Some(TYPST_VERSION));
+        assert!(supported.validate(&spec).is_ok());
+        let newer = manifest("my-package", "0.1.0", Some("99.0.0"));
+        assert!(newer.validate(&spec).is_err());
+    }
+}
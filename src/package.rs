@@ -1,174 +1,750 @@
-use crate::compat::{WasmPackageSpec, WasmVersion};
-use crate::lfs::LFS;
-use js_sys::{Array, ArrayBuffer, Promise, Uint8Array};
-use regex::Regex;
-
+use crate::compat::WasmPackageSpec;
+use crate::lock::{files_hash, sha256, PackageHashes};
+use crate::manifest::{PackageManifest, WasmPackageInfo};
+use crate::storage::{default_storage, FileStorage, StorageBackend, StorageError};
+use js_sys::{Array, ArrayBuffer, Object, Promise, Uint8Array};
 
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::rc::Rc;
 use std::string::String;
-use std::sync::Arc;
 use std::{
     io::Read,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 use typst::{
-    diag::{PackageError, PackageResult},
-    syntax::{PackageSpec},
+    diag::{EcoString, PackageError, PackageResult},
+    syntax::{ast, PackageSpec, SyntaxNode},
 };
 use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
 
 use web_sys::console;
-use web_sys::{Request, RequestInit, RequestMode, Response};
+use web_sys::{Request, RequestInit, RequestMode, Response, Window, WorkerGlobalScope};
 
-pub fn prepare_package(spec: &PackageSpec) -> PackageResult<PathBuf> {
-    if spec.namespace != "preview" {
+pub fn prepare_package(storage: &dyn FileStorage, spec: &PackageSpec) -> PackageResult<PathBuf> {
+    if spec.namespace != "preview" && spec.namespace != "local" {
         return PackageResult::Err(PackageError::Other(None));
     }
     let subdir = format!("packages/{}/{}/{}", spec.namespace, spec.name, spec.version);
     let subdir_key = subdir.clone() + "/.";
-    if !LFS::new().exists(&subdir_key) {
+    if !storage.exists(&subdir_key) {
         console::log_1(&"Package does not exist".into());
         return PackageResult::Err(PackageError::NotFound(spec.clone()));
     }
     PackageResult::Ok(Path::new(&subdir).to_owned())
 }
 
+/// Fetches from the current global scope, which may be a window or a worker.
+fn fetch(request: &Request) -> Promise {
+    let global = js_sys::global();
+    if let Some(window) = global.dyn_ref::<Window>() {
+        window.fetch_with_request(request)
+    } else if let Some(scope) = global.dyn_ref::<WorkerGlobalScope>() {
+        scope.fetch_with_request(request)
+    } else {
+        Promise::reject(&JsValue::from_str("No fetch in this global scope"))
+    }
+}
+
+/// Why a package could not be installed.
+#[derive(Clone, Debug)]
+pub enum InstallError {
+    /// The spec is not of the form `@namespace/name:version`.
+    InvalidSpec(String),
+    /// None of the registries provides the package.
+    NotFound(String),
+    /// The registry could not be reached.
+    Network(String),
+    /// The registry responded with an unexpected status code.
+    HttpStatus(u16),
+    /// The archive is no valid `.tar.gz` file.
+    ArchiveCorrupt(String),
+    /// The manifest is missing or invalid, or does not match the package.
+    InvalidPackage(String),
+    Storage(StorageError),
+}
+
+impl InstallError {
+    /// A stable identifier of the error for JavaScript.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            InstallError::InvalidSpec(_) => "invalid_spec",
+            InstallError::NotFound(_) => "not_found",
+            InstallError::Network(_) => "network",
+            InstallError::HttpStatus(_) => "http_status",
+            InstallError::ArchiveCorrupt(_) => "archive_corrupt",
+            InstallError::InvalidPackage(_) => "invalid_package",
+            InstallError::Storage(StorageError::Full) => "storage_full",
+            InstallError::Storage(_) => "storage",
+        }
+    }
+
+    /// Whether another registry may succeed, where this one failed.
+    fn try_next_registry(&self) -> bool {
+        !matches!(
+            self,
+            InstallError::InvalidSpec(_) | InstallError::Storage(_)
+        )
+    }
+}
+
+impl Display for InstallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InstallError::InvalidSpec(spec) => write!(
+                f,
+                "{} is no valid package spec, expected @namespace/name:version",
+                spec
+            ),
+            InstallError::NotFound(spec) => write!(f, "package {} was not found", spec),
+            InstallError::Network(message) => write!(f, "network error: {}", message),
+            InstallError::HttpStatus(status) => {
+                write!(f, "registry responded with status {}", status)
+            }
+            InstallError::ArchiveCorrupt(message) => write!(f, "invalid archive: {}", message),
+            InstallError::InvalidPackage(message) => f.write_str(message),
+            InstallError::Storage(error) => error.fmt(f),
+        }
+    }
+}
+
+impl From<StorageError> for InstallError {
+    fn from(value: StorageError) -> Self {
+        InstallError::Storage(value)
+    }
+}
+
+/// The value, which rejected promises and thrown exceptions of the `PackageManager` carry.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug)]
+pub struct WasmInstallError {
+    /// One of `invalid_spec`, `not_found`, `network`, `http_status`, `archive_corrupt`,
+    /// `invalid_package`, `storage_full` and `storage`.
+    pub kind: String,
+    pub message: String,
+    /// The status code of `http_status` errors.
+    pub status: Option<u16>,
+}
+
+impl From<InstallError> for JsValue {
+    fn from(value: InstallError) -> Self {
+        let status = match value {
+            InstallError::HttpStatus(status) => Some(status),
+            _ => None,
+        };
+        WasmInstallError {
+            kind: value.kind().to_owned(),
+            message: value.to_string(),
+            status,
+        }
+        .into()
+    }
+}
+
+#[wasm_bindgen]
+impl WasmInstallError {
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_string_js(&self) -> String {
+        self.message.clone()
+    }
+}
+
 #[wasm_bindgen]
 pub struct PackageManager {
-    lfs: Arc<LFS>,
+    storage: Rc<dyn FileStorage>,
+    /// The registry followed by its mirrors, without trailing slashes.
+    registries: Vec<String>,
 }
 
-const PACKAGE_PATH_SPEC_REGEX: &str = r"packages/([^/]+)/([^-]+)/([0-9]+).([0-9]+).([0-9]+)";
-const PACKAGE_SPEC_REGEX: &str = r"@([^/]+)/([^-]+):([0-9]+).([0-9]+).([0-9]+)";
+pub(crate) const DEFAULT_REGISTRY: &str = "https://packages.typst.org";
 
 #[wasm_bindgen]
 impl PackageManager {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Self {
         Self {
-            lfs: Arc::new(LFS::new()),
+            storage: default_storage(),
+            registries: vec![DEFAULT_REGISTRY.to_owned()],
+        }
+    }
+
+    pub fn with_storage(storage: &StorageBackend) -> Self {
+        Self {
+            storage: storage.storage(),
+            registries: vec![DEFAULT_REGISTRY.to_owned()],
         }
     }
 
     pub fn list_packages(&self) -> Array {
-        let keys: Vec<String> = self.lfs.list();
-        let installed_package_paths: Vec<String> = keys
+        installed_packages(&*self.storage)
             .into_iter()
-            .filter(|key| key.starts_with("packages/") && key.ends_with("/."))
-            .collect();
-        let packages: Vec<WasmPackageSpec> = installed_package_paths
-            .into_iter()
-            .map(|path: String| {
-                let captures_regex = Regex::new(PACKAGE_PATH_SPEC_REGEX).unwrap();
-                let captures = captures_regex.captures(&path).unwrap();
-                let namespace = captures[1].to_owned();
-                let name = captures[2].to_owned();
-                let major = captures[3].parse().expect("");
-                let minor = captures[4].parse().expect("");
-                let patch = captures[5].parse().expect("");
-                let version = WasmVersion::new(major, minor, patch);
-                WasmPackageSpec::new(namespace, name, version)
-            })
-            .collect();
-        packages.into_iter().map(JsValue::from).collect()
+            .map(|spec| JsValue::from(WasmPackageSpec::from(spec)))
+            .collect()
     }
 
-    pub fn delete_package(&self, pkg: WasmPackageSpec) {
-        let package_path = format!(
-            "packages/{}/{}-{}.{}.{}",
-            pkg.namespace, pkg.name, pkg.version.major, pkg.version.minor, pkg.version.patch
-        );
-        let keys: Vec<String> = self.lfs.list();
-        let package_keys: Vec<String> = keys
-            .into_iter()
-            .filter(|key| key.starts_with(&package_path))
+    /// Removes every file of an installed package, returns the number of bytes freed.
+    pub fn delete_package(&self, spec: &WasmPackageSpec) -> usize {
+        remove_package(&*self.storage, spec)
+    }
+
+    /// Removes the packages, which are neither in `used` nor a dependency of a used package.
+    ///
+    /// `used` contains specs as `@namespace/name:version`, e.g. from
+    /// `SystemWorld::imported_packages`. Local packages are never removed, since they cannot be
+    /// downloaded again. Returns the number of bytes freed.
+    pub fn remove_unused_packages(&self, used: Array) -> usize {
+        let mut keep: Vec<PackageSpec> = vec![];
+        let mut queue: Vec<PackageSpec> = used
+            .iter()
+            .filter_map(|spec| spec.as_string()?.parse().ok())
             .collect();
-        for key in package_keys {
-            self.lfs.delete(&key);
+        while let Some(spec) = queue.pop() {
+            if keep.contains(&spec) {
+                continue;
+            }
+            queue.extend(dependencies(&installed_files(&*self.storage, &spec)));
+            keep.push(spec);
         }
+        installed_packages(&*self.storage)
+            .into_iter()
+            .filter(|spec| spec.namespace != "local" && !keep.contains(spec))
+            .map(|spec| remove_package(&*self.storage, &spec.into()))
+            .sum()
     }
 
+    /// Like `download_package`, but takes the spec as `@namespace/name:version`.
     pub fn download_package_from_str(&self, spec: &str) -> Promise {
-        let captures_regex = Regex::new(PACKAGE_SPEC_REGEX).unwrap();
-        let captures = captures_regex.captures(&spec).unwrap();
-        let namespace = captures[1].to_owned();
-        let name = captures[2].to_owned();
-        let major = captures[3].parse().expect("");
-        let minor = captures[4].parse().expect("");
-        let patch = captures[5].parse().expect("");
-        let version = WasmVersion::new(major, minor, patch);
-        let spec = WasmPackageSpec::new(namespace, name, version);
-        self.download_package(&spec)
+        match spec.trim().parse::<PackageSpec>() {
+            Ok(spec) => self.download_package(&spec.into()),
+            Err(_) => Promise::reject(&InstallError::InvalidSpec(spec.to_owned()).into()),
+        }
+    }
+
+    /// The metadata from the manifest of an installed package.
+    pub fn package_info(&self, spec: &WasmPackageSpec) -> Option<WasmPackageInfo> {
+        let key = format!("{}/typst.toml", spec.package_directory());
+        let manifest = PackageManifest::parse(&self.storage.get_bytes(&key)?).ok()?;
+        Some(manifest.package.into())
+    }
+
+    /// The base URL of the package registry, e.g. `https://packages.typst.org`.
+    #[wasm_bindgen(getter)]
+    pub fn registry(&self) -> String {
+        self.registries[0].clone()
+    }
+
+    /// Packages are downloaded from `{registry}/{namespace}/{name}-{version}.tar.gz`.
+    #[wasm_bindgen(setter)]
+    pub fn set_registry(&mut self, registry: &str) {
+        self.registries[0] = registry.trim_end_matches('/').to_owned();
+    }
+
+    /// The base URLs which are tried in order, if the registry cannot be reached.
+    #[wasm_bindgen(getter)]
+    pub fn mirrors(&self) -> Array {
+        self.registries[1..]
+            .iter()
+            .map(|mirror| JsValue::from_str(mirror))
+            .collect()
     }
 
+    #[wasm_bindgen(setter)]
+    pub fn set_mirrors(&mut self, mirrors: Array) {
+        self.registries.truncate(1);
+        mirrors.for_each(&mut |mirror: JsValue, _, _| {
+            if let Some(mirror) = mirror.as_string() {
+                self.registries
+                    .push(mirror.trim_end_matches('/').to_owned());
+            }
+        });
+    }
+
+    /// Installs a package into the `@local` namespace from a `.tar.gz` archive.
+    ///
+    /// The archive has to contain a `typst.toml`, whose name and version determine the spec.
+    ///
+    /// The promise resolves to the `WasmPackageSpec` of the installed package.
+    pub fn install_local_archive(&self, archive: ArrayBuffer) -> Promise {
+        let storage = self.storage.clone();
+        let content = Uint8Array::new(&archive).to_vec();
+        future_to_promise(async move {
+            let files = read_archive(&content)?;
+            let spec = install_local(&*storage, files, Some(sha256(&content))).await?;
+            Ok(spec.into())
+        })
+    }
+
+    /// Like `install_local_archive`, but takes an object, which maps the relative paths of the
+    /// package files to `ArrayBuffer`s.
+    pub fn install_local_files(&self, files: Object) -> Promise {
+        let storage = self.storage.clone();
+        future_to_promise(async move {
+            let files = Object::entries(&files)
+                .iter()
+                .map(|entry| {
+                    let entry: Array = entry.dyn_into()?;
+                    let path = entry
+                        .get(0)
+                        .as_string()
+                        .ok_or_else(|| JsValue::from_str("file paths have to be strings"))?;
+                    let content: ArrayBuffer = entry.get(1).dyn_into()?;
+                    Ok((PathBuf::from(path), Uint8Array::new(&content).to_vec()))
+                })
+                .collect::<Result<Vec<_>, JsValue>>()?;
+            let spec = install_local(&*storage, files, None).await?;
+            Ok(spec.into())
+        })
+    }
+
+    /// Downloads a package together with every package it depends on, which is not installed.
+    ///
+    /// The promise is rejected with a `WasmInstallError`. A package, which failed to install, is
+    /// not stored at all, but its dependencies, which were installed before, are kept.
     pub fn download_package(&self, spec: &WasmPackageSpec) -> Promise {
-        // The `@preview` namespace is the only namespace that supports on-demand
-        // fetching.
-        if self.lfs.exists(&spec.package_directory_key()) {
+        if self.storage.exists(&spec.package_directory_key()) {
             return Promise::resolve(&JsValue::from_str("The package already exists"));
         }
-        let package_dir = spec.package_directory();
-        let package_dir = Path::new(&package_dir);
-        assert_eq!(spec.namespace, "preview");
+        self.install_dependencies(spec)
+    }
+
+    /// Downloads the packages, which an installed package depends on directly or indirectly, but
+    /// which are not installed.
+    pub fn install_dependencies(&self, spec: &WasmPackageSpec) -> Promise {
+        let storage = self.storage.clone();
+        let registries = self.registries.clone();
+        let spec = PackageSpec::from(spec);
+        future_to_promise(async move {
+            install_packages(&*storage, &registries, vec![spec]).await?;
+            Ok(JsValue::null())
+        })
+    }
+
+    /// The packages, which the sources of an installed package import directly.
+    pub fn package_dependencies(&self, spec: &WasmPackageSpec) -> Array {
+        let sources = installed_files(&*self.storage, &PackageSpec::from(spec));
+        dependencies(&sources)
+            .into_iter()
+            .map(|spec| JsValue::from(WasmPackageSpec::from(spec)))
+            .collect()
+    }
+}
+
+/// Installs packages together with every package they depend on, which is not installed.
+pub(crate) async fn install_packages(
+    storage: &dyn FileStorage,
+    registries: &[String],
+    mut queue: Vec<PackageSpec>,
+) -> Result<(), InstallError> {
+    let mut visited = vec![];
+    while let Some(spec) = queue.pop() {
+        if visited.contains(&spec) {
+            continue;
+        }
+        let dependencies = if prepare_package(storage, &spec).is_ok() {
+            dependencies(&installed_files(storage, &spec))
+        } else {
+            dependencies(&download(storage, registries, &spec).await?)
+        };
+        queue.extend(dependencies);
+        visited.push(spec);
+    }
+    Ok(())
+}
 
-        let closure_package_dir = package_dir.to_owned();
+/// The specs of all installed packages, keys which are no valid package directory are skipped.
+fn installed_packages(storage: &dyn FileStorage) -> Vec<PackageSpec> {
+    storage
+        .list()
+        .into_iter()
+        .filter_map(|key| {
+            let directory = key.strip_prefix("packages/")?.strip_suffix("/.")?;
+            let spec = directory_spec(directory);
+            if spec.is_none() {
+                console::warn_1(&format!("Skipping invalid package key {}", key).into());
+            }
+            spec
+        })
+        .collect()
+}
 
+/// The installed packages with the descriptions from their manifests.
+pub(crate) fn package_descriptions(
+    storage: &dyn FileStorage,
+) -> Vec<(PackageSpec, Option<EcoString>)> {
+    installed_packages(storage)
+        .into_iter()
+        .map(|spec| {
+            let directory = WasmPackageSpec::from(spec.clone()).package_directory();
+            let description = storage
+                .get_bytes(&format!("{}/typst.toml", directory))
+                .and_then(|manifest| PackageManifest::parse(&manifest).ok())
+                .and_then(|manifest| manifest.package.description)
+                .map(EcoString::from);
+            (spec, description)
+        })
+        .collect()
+}
+
+/// Removes every key of one exact package version, returns the number of bytes freed.
+fn remove_package(storage: &dyn FileStorage, spec: &WasmPackageSpec) -> usize {
+    // The marker is removed first, such that a partially removed package is not considered
+    // installed anymore.
+    std::iter::once(spec.package_directory_key())
+        .chain(package_keys(storage, spec))
+        .map(|key| {
+            let size = storage.stored_size(&key);
+            if storage.delete(&key) {
+                size
+            } else {
+                0
+            }
+        })
+        .sum()
+}
+
+/// The keys of a package's files, without its marker.
+fn package_keys(storage: &dyn FileStorage, spec: &WasmPackageSpec) -> Vec<String> {
+    let marker = spec.package_directory_key();
+    let prefix = format!("{}/", spec.package_directory());
+    storage
+        .list()
+        .into_iter()
+        .filter(|key| key.starts_with(&prefix) && *key != marker)
+        .collect()
+}
+
+/// The spec of a package directory `{namespace}/{name}/{version}`, as stored under `packages/`.
+fn directory_spec(directory: &str) -> Option<PackageSpec> {
+    let mut parts = directory.split('/');
+    let (namespace, name, version) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+    format!("@{}/{}:{}", namespace, name, version).parse().ok()
+}
+
+/// Downloads and installs a package from the first registry, which provides a valid archive.
+///
+/// Returns the files of the package.
+async fn download(
+    storage: &dyn FileStorage,
+    registries: &[String],
+    spec: &PackageSpec,
+) -> Result<Vec<(PathBuf, Vec<u8>)>, InstallError> {
+    // The `@preview` namespace is the only namespace that supports on-demand
+    // fetching.
+    if spec.namespace != "preview" {
+        return Err(InstallError::NotFound(spec.to_string()));
+    }
+    let mut last_error = InstallError::Network("no package registry is configured".to_owned());
+    for registry in registries {
         let url = format!(
-            "https://packages.typst.org/preview/{}-{}.tar.gz",
-            spec.name, spec.version
+            "{}/{}/{}-{}.tar.gz",
+            registry, spec.namespace, spec.name, spec.version
         );
-        let mut opts = RequestInit::new();
-        opts.method("GET");
-        opts.mode(RequestMode::Cors);
-        let request = Request::new_with_str_and_init(&url, &opts)
-            .map_err(|_| PackageError::Other(None))
-            .expect("Could not send request");
-        let _lfs = self.lfs.clone();
-
-        return Promise::new(&mut move |resolve, _reject| {
-            let closure_package_dir = closure_package_dir.clone();
-            let lfs = self.lfs.clone();
-            let window = web_sys::window().expect("Could not get window");
-            let dl_promise = window.fetch_with_request(&request);
-            let dl_closure = Closure::<dyn FnMut(JsValue)>::new(move |resp_value: JsValue| {
-                let closure_package_dir = closure_package_dir.clone();
-                let lfs = lfs.clone();
-                assert!(resp_value.is_instance_of::<Response>());
-                let resp: Response = resp_value.dyn_into().unwrap();
-                let resolve = resolve.clone();
-                let content_promise = resp.array_buffer().expect("Should have a body");
-                let content_closure =
-                    Closure::<dyn FnMut(JsValue)>::new(move |content: JsValue| {
-                        let content: ArrayBuffer = content.dyn_into().unwrap();
-                        let rusty_content = Uint8Array::new(&content).to_vec();
-                        let decompressed = flate2::read::GzDecoder::new(&rusty_content[..]);
-                        let mut archive = tar::Archive::new(decompressed);
-                        archive
-                            .entries()
-                            .expect("Could not read entries")
-                            .into_iter()
-                            .for_each(|entry| {
-                                let entry = entry.expect("Could not read entry");
-                                let path = entry.path().expect("Could not read path");
-                                let package_dir = closure_package_dir.clone();
-                                let file_path = package_dir.join(path);
-                                let file_path =
-                                    file_path.to_str().expect("Could not convert Path to str");
-                                let bytes: Vec<u8> = entry
-                                    .bytes()
-                                    .into_iter()
-                                    .map(|byte| byte.expect("Could not read byte"))
-                                    .collect();
-                                lfs.set_bytes(file_path, &bytes[..]);
-                            });
-                        resolve.call0(&JsValue::null());
-                    });
-                content_promise.then(&content_closure);
-                content_closure.forget();
-            });
-            dl_promise.then(&dl_closure);
-            dl_closure.forget();
+        let result = match fetch_archive(spec, &url).await {
+            Ok(content) => install_archive(storage, spec, &content).await,
+            Err(error) => Err(error),
+        };
+        match result {
+            Ok(files) => return Ok(files),
+            Err(error) if error.try_next_registry() => {
+                console::warn_1(&format!("Could not install {}: {}", url, error).into());
+                last_error = error;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    Err(last_error)
+}
+
+/// The stored files of an installed package with their paths relative to the package directory.
+fn installed_files(storage: &dyn FileStorage, spec: &PackageSpec) -> Vec<(PathBuf, Vec<u8>)> {
+    let spec = WasmPackageSpec::from(spec.clone());
+    let prefix = format!("{}/", spec.package_directory());
+    let marker = spec.package_directory_key();
+    storage
+        .list()
+        .into_iter()
+        .filter(|key| key.starts_with(&prefix) && *key != marker)
+        .filter_map(|key| {
+            let bytes = storage.get_bytes(&key)?;
+            Some((PathBuf::from(&key[prefix.len()..]), bytes))
+        })
+        .collect()
+}
+
+/// Compares the files of an installed package with the hashes, which were recorded on install.
+///
+/// Returns the recorded hashes, packages which were installed before hashes were recorded only
+/// have the hash of their current files.
+pub fn verify_package(
+    storage: &dyn FileStorage,
+    spec: &PackageSpec,
+) -> Result<PackageHashes, String> {
+    let files = installed_files(storage, spec);
+    let actual = files_hash(
+        files
+            .iter()
+            .filter_map(|(path, content)| Some((path.to_str()?, &content[..]))),
+    );
+    let marker = WasmPackageSpec::from(spec.clone()).package_directory_key();
+    let recorded = storage.get_bytes(&marker).unwrap_or_default();
+    if recorded.is_empty() {
+        return Ok(PackageHashes {
+            archive: None,
+            files: actual,
         });
-        // return Promise::resolve(&JsValue::null());
+    }
+    let recorded: PackageHashes = serde_json::from_slice(&recorded).map_err(|_| {
+        format!(
+            "the recorded hashes of {} are corrupted, reinstall it",
+            spec
+        )
+    })?;
+    if recorded.files != actual {
+        return Err(format!(
+            "the files of {} do not match the hashes recorded on install, reinstall it",
+            spec
+        ));
+    }
+    Ok(recorded)
+}
+
+/// The packages, which are imported or included by the Typst sources among `files`.
+fn dependencies(files: &[(PathBuf, Vec<u8>)]) -> Vec<PackageSpec> {
+    let mut specs = vec![];
+    for (path, bytes) in files {
+        if path.extension() != Some(OsStr::new("typ")) {
+            continue;
+        }
+        if let Ok(text) = std::str::from_utf8(bytes) {
+            collect_specs(&typst::syntax::parse(text), &mut specs);
+        }
+    }
+    specs
+}
+
+/// Collects the string literals, which are package specs, e.g. `"@preview/cetz:0.1.2"`.
+pub(crate) fn collect_specs(node: &SyntaxNode, specs: &mut Vec<PackageSpec>) {
+    if let Some(string) = node.cast::<ast::Str>() {
+        let string = string.get();
+        if string.starts_with('@') {
+            if let Ok(spec) = string.parse::<PackageSpec>() {
+                if !specs.contains(&spec) {
+                    specs.push(spec);
+                }
+            }
+        }
+    }
+    for child in node.children() {
+        collect_specs(child, specs);
+    }
+}
+
+/// Installs a package into the `@local` namespace, whose spec is taken from its manifest.
+async fn install_local(
+    storage: &dyn FileStorage,
+    files: Vec<(PathBuf, Vec<u8>)>,
+    archive_hash: Option<String>,
+) -> Result<WasmPackageSpec, InstallError> {
+    let (manifest, files) = package_files(files)?;
+    let spec = format!(
+        "@local/{}:{}",
+        manifest.package.name, manifest.package.version
+    );
+    let spec: PackageSpec = spec
+        .parse()
+        .map_err(|_: EcoString| InstallError::InvalidSpec(spec))?;
+    install_files(storage, &spec, &manifest, &files, archive_hash).await?;
+    Ok(spec.into())
+}
+
+/// Finds and parses the manifest of a package, archives may wrap the package in a directory.
+///
+/// Returns the manifest and the files relative to the package root.
+fn package_files(
+    files: Vec<(PathBuf, Vec<u8>)>,
+) -> Result<(PackageManifest, Vec<(PathBuf, Vec<u8>)>), InstallError> {
+    let (manifest_path, manifest) = files
+        .iter()
+        .filter(|(path, _)| path.file_name() == Some(OsStr::new("typst.toml")))
+        .min_by_key(|(path, _)| path.components().count())
+        .ok_or_else(|| {
+            InstallError::InvalidPackage("the package does not contain a typst.toml".to_owned())
+        })?;
+    let manifest = PackageManifest::parse(manifest).map_err(InstallError::InvalidPackage)?;
+    let root = manifest_path.parent().unwrap_or(Path::new("")).to_owned();
+    let files = files
+        .into_iter()
+        .filter_map(|(path, content)| Some((path.strip_prefix(&root).ok()?.to_owned(), content)))
+        .collect();
+    Ok((manifest, files))
+}
+
+/// Validates a package against its manifest and stores its files together with their hashes.
+///
+/// An installed version of the package is removed first, such that none of its files are left
+/// over. If the package cannot be stored completely, none of its files are kept.
+async fn install_files(
+    storage: &dyn FileStorage,
+    spec: &PackageSpec,
+    manifest: &PackageManifest,
+    files: &[(PathBuf, Vec<u8>)],
+    archive_hash: Option<String>,
+) -> Result<(), InstallError> {
+    manifest
+        .validate(spec)
+        .map_err(InstallError::InvalidPackage)?;
+    let entrypoint = package_file_key(Path::new(""), Path::new(&manifest.package.entrypoint));
+    let has_entrypoint = entrypoint.is_some()
+        && files
+            .iter()
+            .any(|(path, _)| package_file_key(Path::new(""), path) == entrypoint);
+    if !has_entrypoint {
+        return Err(InstallError::InvalidPackage(format!(
+            "the entrypoint {} of the package does not exist",
+            manifest.package.entrypoint
+        )));
+    }
+
+    let spec = WasmPackageSpec::from(spec.clone());
+    let package_dir = PathBuf::from(spec.package_directory());
+    let mut keys = vec![];
+    let mut relative_paths = vec![];
+    for (path, _) in files {
+        let invalid =
+            || InstallError::InvalidPackage(format!("invalid package file {}", path.display()));
+        keys.push(package_file_key(&package_dir, path).ok_or_else(invalid)?);
+        relative_paths.push(package_file_key(Path::new(""), path).ok_or_else(invalid)?);
+    }
+    let hashes = PackageHashes {
+        archive: archive_hash,
+        files: files_hash(
+            relative_paths
+                .iter()
+                .zip(files)
+                .map(|(path, (_, content))| (path.as_str(), &content[..])),
+        ),
+    };
+    let hashes = serde_json::to_vec(&hashes).expect("Hashes are always serializable");
+
+    // Files of a previous installation are only removed once the new one is written, such that
+    // it is kept if the write fails.
+    let stale: Vec<String> = package_keys(storage, &spec)
+        .into_iter()
+        .filter(|key| !keys.contains(key))
+        .collect();
+
+    // The marker is written last, such that half-written packages are not considered installed.
+    let marker = spec.package_directory_key();
+    let values = files.iter().map(|(_, content)| content.clone());
+    let entries = keys.into_iter().zip(values).chain([(marker, hashes)]);
+    storage.set_all(entries.collect()).await?;
+    for key in stale {
+        storage.delete(&key);
+    }
+    Ok(())
+}
+
+/// The storage key of a file in a package, `None` if the path leaves the package directory.
+fn package_file_key(package_dir: &Path, path: &Path) -> Option<String> {
+    let mut key = package_dir.to_owned();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => key.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    key.to_str().map(str::to_owned)
+}
+
+/// Reads the regular files of a `.tar.gz` archive.
+fn read_archive(content: &[u8]) -> Result<Vec<(PathBuf, Vec<u8>)>, InstallError> {
+    let corrupt = |error: std::io::Error| InstallError::ArchiveCorrupt(error.to_string());
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(content));
+    let mut files = vec![];
+    for entry in archive.entries().map_err(corrupt)? {
+        let mut entry = entry.map_err(corrupt)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().map_err(corrupt)?.into_owned();
+        let mut bytes = vec![];
+        entry.read_to_end(&mut bytes).map_err(corrupt)?;
+        files.push((path, bytes));
+    }
+    Ok(files)
+}
+
+/// Downloads a package archive, fails if the registry does not respond with a success status.
+async fn fetch_archive(spec: &PackageSpec, url: &str) -> Result<Vec<u8>, InstallError> {
+    let network = |error: JsValue| {
+        let message = match error.dyn_ref::<js_sys::Error>() {
+            Some(error) => error.message().into(),
+            None => error.as_string().unwrap_or_else(|| format!("{:?}", error)),
+        };
+        InstallError::Network(message)
+    };
+    let mut opts = RequestInit::new();
+    opts.method("GET");
+    opts.mode(RequestMode::Cors);
+    let request = Request::new_with_str_and_init(url, &opts).map_err(network)?;
+    let response: Response = JsFuture::from(fetch(&request))
+        .await
+        .and_then(|response| response.dyn_into())
+        .map_err(network)?;
+    match response.status() {
+        200..=299 => {}
+        404 => return Err(InstallError::NotFound(spec.to_string())),
+        status => return Err(InstallError::HttpStatus(status)),
+    }
+    let content = response.array_buffer().map_err(network)?;
+    let content = JsFuture::from(content).await.map_err(network)?;
+    Ok(Uint8Array::new(&content).to_vec())
+}
+
+async fn install_archive(
+    storage: &dyn FileStorage,
+    spec: &PackageSpec,
+    content: &[u8],
+) -> Result<Vec<(PathBuf, Vec<u8>)>, InstallError> {
+    let (manifest, files) = package_files(read_archive(content)?)?;
+    install_files(storage, spec, &manifest, &files, Some(sha256(content))).await?;
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directory_spec_accepts_hyphenated_names() {
+        let spec = directory_spec("preview/my-package/0.1.0").unwrap();
+        assert_eq!(spec.namespace, "preview");
+        assert_eq!(spec.name, "my-package");
+        assert_eq!(spec.version.to_string(), "0.1.0");
+    }
+
+    #[test]
+    fn directory_spec_rejects_other_directories() {
+        assert!(directory_spec("preview/cetz").is_none());
+        assert!(directory_spec("preview/cetz/0.1.0/src").is_none());
+        assert!(directory_spec("preview/cetz/latest").is_none());
+    }
+
+    #[test]
+    fn package_file_key_stays_in_package() {
+        let dir = Path::new("packages/preview/cetz/0.1.0");
+        assert_eq!(
+            package_file_key(dir, Path::new("./src/lib.typ")).as_deref(),
+            Some("packages/preview/cetz/0.1.0/src/lib.typ")
+        );
+        assert_eq!(package_file_key(dir, Path::new("../other/lib.typ")), None);
+        assert_eq!(package_file_key(dir, Path::new("src/../../lib.typ")), None);
+        assert_eq!(package_file_key(dir, Path::new("/lib.typ")), None);
     }
 }
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use js_sys::Array;
+use once_cell::unsync::OnceCell;
+use typst::doc::{Document, Frame};
+use typst::geom::Color;
+use wasm_bindgen::prelude::*;
+
+use crate::blob_url;
+
+/// A rendered page, which is reused as long as the page's frame does not change.
+pub struct CachedPage {
+    hash: u128,
+    pub png: Vec<u8>,
+    url: OnceCell<String>,
+}
+
+impl CachedPage {
+    fn render(frame: &Frame, hash: u128, pixel_per_pt: f32) -> Self {
+        let image = typst::export::render(frame, pixel_per_pt, Color::WHITE);
+        Self {
+            hash,
+            png: image.encode_png().expect("Could not encode as PNG"),
+            url: OnceCell::new(),
+        }
+    }
+
+    /// A blob URL of the PNG, which stays valid as long as the page is cached.
+    pub fn url(&self) -> Result<String, JsValue> {
+        self.url
+            .get_or_try_init(|| blob_url(&self.png, "image/png"))
+            .cloned()
+    }
+}
+
+impl Drop for CachedPage {
+    fn drop(&mut self) {
+        if let Some(url) = self.url.get() {
+            let _ = web_sys::Url::revoke_object_url(url);
+        }
+    }
+}
+
+/// Keeps the rendered pages of the last compilation, keyed by the hash of their frames.
+#[derive(Default)]
+pub struct PageCache {
+    pixel_per_pt: f32,
+    pages: Vec<Rc<CachedPage>>,
+}
+
+impl PageCache {
+    /// Renders the pages of `document`, which were not rendered before.
+    pub fn render(&mut self, document: &Document, pixel_per_pt: f32) -> RenderedPages {
+        if self.pixel_per_pt != pixel_per_pt {
+            self.pages.clear();
+            self.pixel_per_pt = pixel_per_pt;
+        }
+        let mut previous: HashMap<u128, Rc<CachedPage>> = self
+            .pages
+            .iter()
+            .map(|page| (page.hash, page.clone()))
+            .collect();
+
+        let pages: Vec<Rc<CachedPage>> = document
+            .pages
+            .iter()
+            .map(|frame| {
+                let hash = typst::util::hash128(frame);
+                previous
+                    .remove(&hash)
+                    .unwrap_or_else(|| Rc::new(CachedPage::render(frame, hash, pixel_per_pt)))
+            })
+            .collect();
+        let changed = pages
+            .iter()
+            .enumerate()
+            .filter(|(i, page)| self.pages.get(*i).map_or(true, |old| old.hash != page.hash))
+            .map(|(i, _)| i)
+            .collect();
+
+        // Dropping the pages which are not part of the document anymore revokes their URLs.
+        self.pages = pages.clone();
+        RenderedPages { pages, changed }
+    }
+}
+
+/// The pages of a document rendered to PNG.
+#[wasm_bindgen]
+pub struct RenderedPages {
+    #[wasm_bindgen(skip)]
+    pub pages: Vec<Rc<CachedPage>>,
+    /// The indices of the pages, which differ from the previous compilation.
+    #[wasm_bindgen(skip)]
+    pub changed: Vec<usize>,
+}
+
+#[wasm_bindgen]
+impl RenderedPages {
+    /// One blob URL per page, unchanged pages keep their previous URL.
+    pub fn urls(&self) -> Result<Array, JsValue> {
+        self.pages
+            .iter()
+            .map(|page| page.url().map(|url| JsValue::from_str(&url)))
+            .collect()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn changed(&self) -> Array {
+        self.changed
+            .iter()
+            .map(|&index| JsValue::from(index as u32))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use typst::geom::{Abs, Size};
+
+    use super::*;
+
+    fn document(widths: &[f64]) -> Document {
+        Document {
+            pages: widths
+                .iter()
+                .map(|&width| Frame::new(Size::new(Abs::pt(width), Abs::pt(10.0))))
+                .collect(),
+            ..Document::default()
+        }
+    }
+
+    #[test]
+    fn render_reports_changed_pages() {
+        let mut cache = PageCache::default();
+        let first = cache.render(&document(&[10.0, 20.0]), 1.0);
+        assert_eq!(first.changed, [0, 1]);
+
+        let second = cache.render(&document(&[10.0, 30.0, 20.0]), 1.0);
+        assert_eq!(second.changed, [1, 2]);
+        // Pages, which only moved, are not rendered again.
+        assert!(Rc::ptr_eq(&first.pages[0], &second.pages[0]));
+        assert!(Rc::ptr_eq(&first.pages[1], &second.pages[2]));
+    }
+
+    #[test]
+    fn render_again_after_resolution_change() {
+        let mut cache = PageCache::default();
+        let first = cache.render(&document(&[10.0]), 1.0);
+        let second = cache.render(&document(&[10.0]), 2.0);
+        assert_eq!(second.changed, [0]);
+        assert!(!Rc::ptr_eq(&first.pages[0], &second.pages[0]));
+    }
+}
@@ -0,0 +1,271 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::future::{ready, Future};
+use std::pin::Pin;
+use std::rc::Rc;
+
+use js_sys::Promise;
+use typst::diag::FileError;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
+use web_sys::{IdbFactory, Storage, Window, WorkerGlobalScope};
+
+use crate::idb::IdbStorage;
+use crate::lfs::LFS;
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+/// A write, which asynchronous storages only finish after it was committed.
+pub type StorageFuture<'a> = Pin<Box<dyn Future<Output = StorageResult<()>> + 'a>>;
+
+#[derive(Clone, Debug)]
+pub enum StorageError {
+    /// The backing store could not be accessed at all.
+    Unavailable,
+    /// The backing store refused the write, usually because the quota is used up.
+    Full,
+    /// The stored value could not be decoded.
+    Corrupt(String),
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::Unavailable => f.write_str("storage is not available"),
+            StorageError::Full => f.write_str("storage is full"),
+            StorageError::Corrupt(key) => write!(f, "stored value for {} is corrupt", key),
+        }
+    }
+}
+
+impl From<StorageError> for FileError {
+    fn from(value: StorageError) -> Self {
+        FileError::Other(Some(value.to_string().into()))
+    }
+}
+
+impl From<StorageError> for JsValue {
+    fn from(value: StorageError) -> Self {
+        JsValue::from_str(&value.to_string())
+    }
+}
+
+/// A flat key value store that files and packages are persisted in.
+///
+/// Keys are `/` separated paths, e.g. `packages/preview/cetz/0.1.2/lib.typ`.
+pub trait FileStorage {
+    fn exists(&self, key: &str) -> bool;
+
+    fn get(&self, key: &str) -> Option<String>;
+
+    fn get_bytes(&self, key: &str) -> Option<Vec<u8>>;
+
+    fn set(&self, key: &str, value: &str) -> StorageResult<()>;
+
+    fn set_bytes(&self, key: &str, value: &[u8]) -> StorageResult<()>;
+
+    fn delete(&self, key: &str) -> bool;
+
+    fn list(&self) -> Vec<String>;
+
+    /// The number of bytes, which an entry takes up in the storage, `0` if there is none.
+    fn stored_size(&self, key: &str) -> usize {
+        self.get_bytes(key).map_or(0, |bytes| bytes.len())
+    }
+
+    /// Writes all entries or none of them.
+    ///
+    /// Storages without transactions write the entries in order and restore the previous values,
+    /// if a write fails.
+    fn set_all(&self, entries: Vec<(String, Vec<u8>)>) -> StorageFuture<'_> {
+        let mut previous = vec![];
+        for (key, value) in &entries {
+            let old = self.get_bytes(key);
+            if let Err(error) = self.set_bytes(key, value) {
+                for (key, old) in previous.into_iter().rev() {
+                    match old {
+                        Some(old) => {
+                            let _ = self.set_bytes(key, &old);
+                        }
+                        None => {
+                            self.delete(key);
+                        }
+                    }
+                }
+                return Box::pin(ready(Err(error)));
+            }
+            previous.push((key, old));
+        }
+        Box::pin(ready(Ok(())))
+    }
+
+    /// Loads all values below `prefix`, such that they can be read synchronously afterwards.
+    ///
+    /// Synchronous storages do not need to do anything here.
+    fn prefetch(&self, _prefix: &str) -> Promise {
+        Promise::resolve(&JsValue::UNDEFINED)
+    }
+
+    /// Reads the keys again, which another instance of the same storage may have changed, e.g.
+    /// in another thread. Values have to be prefetched again afterwards.
+    ///
+    /// Storages, which read the keys on every access, do not need to do anything here.
+    fn reload(&self) -> Promise {
+        Promise::resolve(&JsValue::UNDEFINED)
+    }
+}
+
+/// A storage which only lives as long as the wasm instance.
+#[derive(Default)]
+pub struct MemoryStorage {
+    entries: RefCell<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl FileStorage for MemoryStorage {
+    fn exists(&self, key: &str) -> bool {
+        self.entries.borrow().contains_key(key)
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        self.get_bytes(key)
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+    }
+
+    fn get_bytes(&self, key: &str) -> Option<Vec<u8>> {
+        self.entries.borrow().get(key).cloned()
+    }
+
+    fn set(&self, key: &str, value: &str) -> StorageResult<()> {
+        self.set_bytes(key, value.as_bytes())
+    }
+
+    fn set_bytes(&self, key: &str, value: &[u8]) -> StorageResult<()> {
+        self.entries
+            .borrow_mut()
+            .insert(key.to_owned(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        self.entries.borrow_mut().remove(key).is_some()
+    }
+
+    fn list(&self) -> Vec<String> {
+        self.entries.borrow().keys().cloned().collect()
+    }
+}
+
+/// The local storage of the current global scope, if there is one.
+///
+/// Web Workers do not have a local storage.
+pub fn local_storage() -> Option<Storage> {
+    js_sys::global()
+        .dyn_ref::<Window>()?
+        .local_storage()
+        .ok()
+        .flatten()
+}
+
+/// The IndexedDB factory of the current global scope, which may be a window or a worker.
+pub fn indexed_db() -> Option<IdbFactory> {
+    let global = js_sys::global();
+    if let Some(window) = global.dyn_ref::<Window>() {
+        window.indexed_db().ok().flatten()
+    } else if let Some(scope) = global.dyn_ref::<WorkerGlobalScope>() {
+        scope.indexed_db().ok().flatten()
+    } else {
+        None
+    }
+}
+
+/// Uses the local storage where possible and falls back to an in-memory storage.
+pub fn default_storage() -> Rc<dyn FileStorage> {
+    match LFS::new() {
+        Some(lfs) => Rc::new(lfs),
+        None => Rc::new(MemoryStorage::new()),
+    }
+}
+
+/// A storage which can be shared between a `SystemWorld` and a `PackageManager`.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct StorageBackend {
+    inner: Rc<dyn FileStorage>,
+}
+
+impl StorageBackend {
+    pub fn storage(&self) -> Rc<dyn FileStorage> {
+        self.inner.clone()
+    }
+}
+
+#[wasm_bindgen]
+impl StorageBackend {
+    pub fn local() -> Result<StorageBackend, JsValue> {
+        let lfs = LFS::new().ok_or(StorageError::Unavailable)?;
+        Ok(Self {
+            inner: Rc::new(lfs),
+        })
+    }
+
+    pub fn memory() -> StorageBackend {
+        Self {
+            inner: Rc::new(MemoryStorage::new()),
+        }
+    }
+
+    /// Opens an IndexedDB database, which stores binary files without the localStorage overhead.
+    pub async fn indexed_db(name: String) -> Result<StorageBackend, JsValue> {
+        let idb = IdbStorage::open(&name).await?;
+        Ok(Self {
+            inner: Rc::new(idb),
+        })
+    }
+
+    pub fn prefetch(&self, prefix: &str) -> Promise {
+        self.inner.prefetch(prefix)
+    }
+
+    /// Moves the entries under `prefix` from another storage into this one, e.g. packages, which
+    /// were installed into localStorage before IndexedDB was used.
+    ///
+    /// The entries are only deleted from the other storage once all of them were written.
+    pub fn move_from(&self, other: &StorageBackend, prefix: String) -> Promise {
+        let storage = self.inner.clone();
+        let other = other.inner.clone();
+        future_to_promise(async move {
+            JsFuture::from(other.prefetch(&prefix)).await?;
+            let keys: Vec<String> = other
+                .list()
+                .into_iter()
+                .filter(|key| key.starts_with(&prefix))
+                .collect();
+            let entries = keys
+                .iter()
+                .filter_map(|key| Some((key.clone(), other.get_bytes(key)?)))
+                .collect();
+            storage.set_all(entries).await?;
+            for key in &keys {
+                other.delete(key);
+            }
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Reads the keys again and prefetches every value, e.g. after another thread installed
+    /// packages.
+    pub fn reload(&self) -> Promise {
+        let storage = self.inner.clone();
+        future_to_promise(async move {
+            JsFuture::from(storage.reload()).await?;
+            JsFuture::from(storage.prefetch("")).await
+        })
+    }
+}
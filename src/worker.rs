@@ -0,0 +1,360 @@
+//! A compiler which runs inside a dedicated Web Worker.
+//!
+//! Every message in both directions is a plain object with the fields `version`, `id` and `kind`.
+//! Requests:
+//! - `set_source` with `path` and `text`
+//! - `edit` with `path`, `start`, `end` (UTF-16 offsets) and `text`
+//! - `delete_file` with `path`
+//! - `upload_file` with `path` and `content` (an `ArrayBuffer`)
+//! - `add_fonts` with `fonts` (an array of `ArrayBuffer`s)
+//! - `jump_from_click` with `page` (starting at one), `x` and `y` (in points) and
+//!   `jump_from_cursor` with `path` and `cursor` (a byte offset), both use the document of the
+//!   last compilation
+//! - `compile` with `format` (`pdf`, `svg` or `png`) and `pixel_per_pt` for `png`
+//! - `reload`, after another thread changed the storage, e.g. installed packages
+//! - `install` with `packages` (`@namespace/name:version` strings) and optionally `registries`
+//!   (the registry followed by its mirrors), the packages are installed with their dependencies
+//!
+//! Compilations wait until the `reload` and `install` requests before them are finished.
+//!
+//! Responses:
+//! - `compiled` with `format`, `output`, `warnings` and `lock_file`, the text of `/typst.lock` or
+//!   `null`
+//! - `failed` with `diagnostics` and `missing_packages`, the imported packages which are not
+//!   installed, as `@namespace/name:version` strings
+//! - `reloaded` and `installed`, once a `reload` or `install` request is finished
+//! - `uploaded`, once the file of an `upload_file` request is stored
+//! - `jump` with `jump`, where the click leads to, and `position` with `position`, the position
+//!   of the cursor in the document, both may be `null`
+//! - `cancelled`, if a newer `compile` request superseded the one with this `id`
+//! - `error` with `message`, if a request could not be handled
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use js_sys::{Array, ArrayBuffer, Object, Promise, Reflect, Uint8Array};
+use typst::diag::EcoString;
+use typst::syntax::PackageSpec;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{future_to_promise, spawn_local, JsFuture};
+use web_sys::{DedicatedWorkerGlobalScope, MessageEvent};
+
+use crate::diagnostic::{self, set};
+use crate::package::{install_packages, DEFAULT_REGISTRY};
+use crate::storage::StorageBackend;
+use crate::SystemWorld;
+
+/// The version of the message protocol, messages with a different version are rejected.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+struct WorkerState {
+    world: SystemWorld,
+    storage: StorageBackend,
+    /// The compile request, which runs once all queued messages were handled.
+    pending: Option<CompileRequest>,
+    /// Settles once the requested reloads and installations are finished.
+    ready: Promise,
+}
+
+struct CompileRequest {
+    id: JsValue,
+    format: String,
+    pixel_per_pt: f32,
+}
+
+/// Handles the messages of the worker's global scope from now on.
+#[wasm_bindgen]
+pub fn start_compile_worker(storage: &StorageBackend) -> Result<(), JsValue> {
+    let scope: DedicatedWorkerGlobalScope = js_sys::global().dyn_into()?;
+    let state = Rc::new(RefCell::new(WorkerState {
+        world: SystemWorld::with_storage(storage),
+        storage: storage.clone(),
+        pending: None,
+        ready: Promise::resolve(&JsValue::UNDEFINED),
+    }));
+    let handler_scope = scope.clone();
+    let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+        let data = event.data();
+        if let Err(error) = handle_message(&state, &handler_scope, &data) {
+            let id = Reflect::get(&data, &"id".into()).unwrap_or(JsValue::NULL);
+            let response = message("error", &id);
+            set(&response, "message", &error_message(&error));
+            post(&handler_scope, &response);
+        }
+    });
+    scope.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    on_message.forget();
+    Ok(())
+}
+
+fn handle_message(
+    state: &Rc<RefCell<WorkerState>>,
+    scope: &DedicatedWorkerGlobalScope,
+    data: &JsValue,
+) -> Result<(), JsValue> {
+    let version = get(data, "version")?.as_f64();
+    if version != Some(PROTOCOL_VERSION as f64) {
+        return Err(JsValue::from_str(&format!(
+            "unsupported protocol version, expected {}",
+            PROTOCOL_VERSION
+        )));
+    }
+    let id = get(data, "id")?;
+    let kind = get_string(data, "kind")?;
+    let mut state_ref = state.borrow_mut();
+    let world = &mut state_ref.world;
+    match kind.as_str() {
+        "set_source" => {
+            world.set_source(&get_string(data, "path")?, get_string(data, "text")?);
+        }
+        "edit" => {
+            world.edit_utf16(
+                &get_string(data, "path")?,
+                get_number(data, "start")? as usize,
+                get_number(data, "end")? as usize,
+                &get_string(data, "text")?,
+            )?;
+        }
+        "delete_file" => world.delete_file(&get_string(data, "path")?)?,
+        "upload_file" => {
+            let content: ArrayBuffer = get(data, "content")?.dyn_into()?;
+            let saved = world.upload_file(&get_string(data, "path")?, content)?;
+            report(scope, id, "uploaded", saved);
+        }
+        "add_fonts" => world.add_fonts(get(data, "fonts")?.dyn_into()?),
+        "jump_from_click" => {
+            let jump = world.jump_from_click(
+                get_number(data, "page")? as usize,
+                get_number(data, "x")?,
+                get_number(data, "y")?,
+            );
+            let response = message("jump", &id);
+            let jump = jump.map_or(JsValue::NULL, |jump| jump.to_object().into());
+            set(&response, "jump", &jump);
+            post(scope, &response);
+        }
+        "jump_from_cursor" => {
+            let position = world.jump_from_cursor(
+                &get_string(data, "path")?,
+                get_number(data, "cursor")? as usize,
+            )?;
+            let response = message("position", &id);
+            let position = position.map_or(JsValue::NULL, |position| position.to_object().into());
+            set(&response, "position", &position);
+            post(scope, &response);
+        }
+        "reload" => {
+            let reloaded = state_ref.storage.reload();
+            wait_before_compile(&mut state_ref, scope, id, "reloaded", reloaded);
+        }
+        "install" => {
+            let packages: Array = get(data, "packages")?.dyn_into()?;
+            let specs = packages
+                .iter()
+                .map(|spec| {
+                    let spec = spec.as_string().unwrap_or_default();
+                    spec.parse().map_err(|_: EcoString| {
+                        JsValue::from_str(&format!("invalid package spec {}", spec))
+                    })
+                })
+                .collect::<Result<Vec<PackageSpec>, JsValue>>()?;
+            let registries = match get(data, "registries")?.dyn_into::<Array>() {
+                Ok(registries) => registries
+                    .iter()
+                    .filter_map(|registry| registry.as_string())
+                    .map(|registry| registry.trim_end_matches('/').to_owned())
+                    .collect(),
+                Err(_) => vec![DEFAULT_REGISTRY.to_owned()],
+            };
+            let storage = state_ref.storage.storage();
+            let installed = future_to_promise(async move {
+                install_packages(&*storage, &registries, specs)
+                    .await
+                    .map_err(|error| JsValue::from_str(&error.to_string()))?;
+                Ok(JsValue::UNDEFINED)
+            });
+            wait_before_compile(&mut state_ref, scope, id, "installed", installed);
+        }
+        "compile" => {
+            let request = CompileRequest {
+                id,
+                format: get_string(data, "format")?,
+                pixel_per_pt: get(data, "pixel_per_pt")?.as_f64().unwrap_or(2.0) as f32,
+            };
+            match state_ref.pending.replace(request) {
+                Some(superseded) => post(scope, &message("cancelled", &superseded.id)),
+                None => schedule_compile(state, scope)?,
+            }
+        }
+        _ => return Err(JsValue::from_str(&format!("unknown message kind {}", kind))),
+    }
+    Ok(())
+}
+
+/// Compiles after the messages, which are already queued, were handled.
+///
+/// Newer edits are thereby applied first and newer compile requests replace the pending one.
+fn schedule_compile(
+    state: &Rc<RefCell<WorkerState>>,
+    scope: &DedicatedWorkerGlobalScope,
+) -> Result<(), JsValue> {
+    let state = state.clone();
+    let callback_scope = scope.clone();
+    let callback = Closure::once_into_js(move || {
+        let ready = state.borrow().ready.clone();
+        spawn_local(async move {
+            // Failed reloads and installations were already reported.
+            let _ = JsFuture::from(ready).await;
+            let mut state = state.borrow_mut();
+            if let Some(request) = state.pending.take() {
+                let response = compile(&mut state.world, &request);
+                post(&callback_scope, &response);
+            }
+        });
+    });
+    scope.set_timeout_with_callback(callback.unchecked_ref())?;
+    Ok(())
+}
+
+/// Compilations wait until `promise` is settled, its outcome is posted as `kind` or `error`.
+fn wait_before_compile(
+    state: &mut WorkerState,
+    scope: &DedicatedWorkerGlobalScope,
+    id: JsValue,
+    kind: &'static str,
+    promise: Promise,
+) {
+    state.ready = Promise::all_settled(&Array::of2(&state.ready, &promise));
+    report(scope, id, kind, promise);
+}
+
+/// Posts `kind` once `promise` is fulfilled, or `error` if it is rejected.
+fn report(scope: &DedicatedWorkerGlobalScope, id: JsValue, kind: &'static str, promise: Promise) {
+    let scope = scope.clone();
+    spawn_local(async move {
+        let response = match JsFuture::from(promise).await {
+            Ok(_) => message(kind, &id),
+            Err(error) => {
+                let response = message("error", &id);
+                set(&response, "message", &error_message(&error));
+                response
+            }
+        };
+        post(&scope, &response);
+    });
+}
+
+fn compile(world: &mut SystemWorld, request: &CompileRequest) -> Object {
+    let result = match request.format.as_str() {
+        "pdf" => world
+            .compile_to_pdf_bytes(None)
+            .map(|compiled| compiled.map(|pdf| JsValue::from(Uint8Array::from(&pdf[..])))),
+        "svg" => world.compile_to_svg_strings(None).map(|compiled| {
+            compiled.map(|svgs| {
+                let svgs: Array = svgs.iter().map(|svg| JsValue::from_str(svg)).collect();
+                svgs.into()
+            })
+        }),
+        "png" => world
+            .compile_to_images_bytes(None, request.pixel_per_pt)
+            .map(|compiled| {
+                compiled.map(|rendered| {
+                    // Only changed pages are sent, the others are `null`.
+                    let pages: Array = rendered
+                        .pages
+                        .iter()
+                        .enumerate()
+                        .map(|(i, page)| {
+                            if rendered.changed.contains(&i) {
+                                Uint8Array::from(&page.png[..]).into()
+                            } else {
+                                JsValue::NULL
+                            }
+                        })
+                        .collect();
+                    let output = Object::new();
+                    set(&output, "pages", &pages);
+                    set(&output, "changed", &rendered.changed());
+                    output.into()
+                })
+            }),
+        format => {
+            let response = message("error", &request.id);
+            set(
+                &response,
+                "message",
+                &JsValue::from_str(&format!("unknown format {}", format)),
+            );
+            return response;
+        }
+    };
+
+    match result {
+        Ok(compiled) => {
+            let response = message("compiled", &request.id);
+            set(&response, "format", &JsValue::from_str(&request.format));
+            set(&response, "output", &compiled.output);
+            set(
+                &response,
+                "warnings",
+                &diagnostic::to_plain_array(&compiled.warnings),
+            );
+            let lock_file = world.lock_file().map_or(JsValue::NULL, JsValue::from);
+            set(&response, "lock_file", &lock_file);
+            response
+        }
+        Err(errors) => {
+            let response = message("failed", &request.id);
+            set(
+                &response,
+                "diagnostics",
+                &diagnostic::to_plain_array(&errors),
+            );
+            let missing: Array = world
+                .vfs
+                .missing_packages()
+                .iter()
+                .map(|spec| JsValue::from_str(&spec.to_string()))
+                .collect();
+            set(&response, "missing_packages", &missing);
+            response
+        }
+    }
+}
+
+fn message(kind: &str, id: &JsValue) -> Object {
+    let object = Object::new();
+    set(&object, "version", &JsValue::from(PROTOCOL_VERSION));
+    set(&object, "id", id);
+    set(&object, "kind", &JsValue::from_str(kind));
+    object
+}
+
+fn post(scope: &DedicatedWorkerGlobalScope, message: &Object) {
+    scope
+        .post_message(message)
+        .expect("Could not post message to the main thread");
+}
+
+fn error_message(error: &JsValue) -> JsValue {
+    match error.dyn_ref::<js_sys::Error>() {
+        Some(error) => error.message().into(),
+        None => error.clone(),
+    }
+}
+
+fn get(data: &JsValue, key: &str) -> Result<JsValue, JsValue> {
+    Reflect::get(data, &JsValue::from_str(key))
+}
+
+fn get_string(data: &JsValue, key: &str) -> Result<String, JsValue> {
+    get(data, key)?
+        .as_string()
+        .ok_or_else(|| JsValue::from_str(&format!("{} has to be a string", key)))
+}
+
+fn get_number(data: &JsValue, key: &str) -> Result<f64, JsValue> {
+    get(data, key)?
+        .as_f64()
+        .ok_or_else(|| JsValue::from_str(&format!("{} has to be a number", key)))
+}